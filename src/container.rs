@@ -0,0 +1,180 @@
+use crate::inventory::{self, ItemList};
+use crate::{get_variant_mut, NBTValue, ValueType};
+use anyhow::{bail, Context, Result};
+use std::cmp::Reverse;
+
+/// Where container items keep their contents, for both the old item tags and the 1.20.5+
+/// item components.
+const CONTENT_PATHS: &[&[&str]] = &[
+    &["tag", "BlockEntityTag", "Items"],
+    &["tag", "Items"],
+    &["components", "minecraft:container"],
+    &["components", "minecraft:bundle_contents"],
+];
+
+/// An item stored inside a container item such as a shulker box or bundle.
+#[derive(Debug, Clone)]
+pub struct NestedEntry {
+    /// Positions of the entry in each container's contents, outermost first
+    pub path: Vec<usize>,
+    /// Value of the entry's `Slot` or `slot` tag, if it has one
+    pub slot: Option<i32>,
+    pub size: usize,
+    pub id: String,
+}
+
+fn child<'a>(value: &'a NBTValue, name: &str) -> Option<&'a NBTValue> {
+    match &value.ty {
+        ValueType::Compound(compound) => compound.get(name),
+        _ => None,
+    }
+}
+
+fn child_mut<'a>(value: &'a mut NBTValue, name: &str) -> Option<&'a mut NBTValue> {
+    match &mut value.ty {
+        ValueType::Compound(compound) => compound.get_mut(name),
+        _ => None,
+    }
+}
+
+/// Returns the contents of a container item, or `None` if the item holds no other items.
+pub fn contents(item: &NBTValue) -> Option<&Vec<NBTValue>> {
+    CONTENT_PATHS.iter().find_map(|path| {
+        let value = path
+            .iter()
+            .try_fold(item, |value, name| child(value, name))?;
+        match &value.ty {
+            ValueType::List(list) => Some(&list.items),
+            _ => None,
+        }
+    })
+}
+
+pub fn contents_mut(item: &mut NBTValue) -> Option<&mut Vec<NBTValue>> {
+    let path = CONTENT_PATHS.iter().find(|path| {
+        let value = path
+            .iter()
+            .try_fold(&*item, |value, name| child(value, name));
+        matches!(value.map(|value| &value.ty), Some(ValueType::List(_)))
+    })?;
+    let value = path
+        .iter()
+        .try_fold(item, |value, name| child_mut(value, name))?;
+    match &mut value.ty {
+        ValueType::List(list) => Some(&mut list.items),
+        _ => None,
+    }
+}
+
+/// `minecraft:container` entries wrap the item in a compound along with its slot.
+fn is_wrapped(entry: &NBTValue) -> bool {
+    child(entry, "id").is_none() && child(entry, "item").is_some()
+}
+
+fn unwrap_entry(entry: &NBTValue) -> &NBTValue {
+    if is_wrapped(entry) {
+        child(entry, "item").unwrap()
+    } else {
+        entry
+    }
+}
+
+fn unwrap_entry_mut(entry: &mut NBTValue) -> &mut NBTValue {
+    if is_wrapped(entry) {
+        child_mut(entry, "item").unwrap()
+    } else {
+        entry
+    }
+}
+
+/// Returns the id of the item held by a container entry.
+pub fn item_id(entry: &NBTValue) -> Option<&str> {
+    match &child(unwrap_entry(entry), "id")?.ty {
+        ValueType::String(id) => Some(id),
+        _ => None,
+    }
+}
+
+/// Takes the item out of a container entry, dropping the `minecraft:container` wrapper.
+pub fn into_item(entry: NBTValue) -> NBTValue {
+    if !is_wrapped(&entry) {
+        return entry;
+    }
+    match entry.ty {
+        ValueType::Compound(mut compound) => compound.shift_remove("item").unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+fn entry_slot(entry: &NBTValue) -> Option<i32> {
+    let slot = child(entry, "Slot").or_else(|| child(entry, "slot"))?;
+    match slot.ty {
+        ValueType::Byte(slot) => Some(slot as i32),
+        ValueType::Int(slot) => Some(slot),
+        _ => None,
+    }
+}
+
+fn collect(item: &NBTValue, path: &mut Vec<usize>, entries: &mut Vec<NestedEntry>) -> Result<()> {
+    let items = match contents(item) {
+        Some(items) => items,
+        None => return Ok(()),
+    };
+    for (index, entry) in items.iter().enumerate() {
+        let id = item_id(entry).context("Nested item has no id")?;
+        path.push(index);
+        entries.push(NestedEntry {
+            path: path.clone(),
+            slot: entry_slot(entry),
+            size: entry.size(),
+            id: id.to_string(),
+        });
+        collect(unwrap_entry(entry), path, entries)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Collects every item nested inside a container item at any depth, ranked from largest to
+/// smallest.
+pub fn rank_nested(item: &NBTValue) -> Result<Vec<NestedEntry>> {
+    let mut entries = Vec::new();
+    collect(item, &mut Vec::new(), &mut entries)?;
+    entries.sort_by_key(|entry| Reverse(entry.size));
+    Ok(entries)
+}
+
+/// Removes the nested entry at `path` from a container item.
+pub fn remove_nested(item: &mut NBTValue, path: &[usize]) -> Result<NBTValue> {
+    let (&last, parents) = match path.split_last() {
+        Some(split) => split,
+        None => bail!("Empty nested item path"),
+    };
+    let mut container = item;
+    for &index in parents {
+        let items = contents_mut(container).context("Item is not a container")?;
+        let entry = items.get_mut(index).context("Nested item not found")?;
+        container = unwrap_entry_mut(entry);
+    }
+    let items = contents_mut(container).context("Item is not a container")?;
+    if last >= items.len() {
+        bail!("Nested item not found");
+    }
+    Ok(items.remove(last))
+}
+
+/// Removes an item nested inside the container in `slot` of one of a player's item lists.
+pub fn remove_player_nested(
+    root: &mut NBTValue,
+    list: ItemList,
+    slot: i8,
+    path: &[usize],
+) -> Result<NBTValue> {
+    let value = inventory::item_list_mut(root, list)?.context("Item list missing")?;
+    let items = get_variant_mut!(value.ty, ValueType::List);
+    let container = items
+        .iter_mut()
+        .find(|item| entry_slot(item) == Some(slot as i32))
+        .context("Slot not found")?;
+    remove_nested(container, path)
+}
@@ -0,0 +1,159 @@
+//! Entity chunks, which since 1.17 are kept apart from the terrain in the region files of the
+//! world's `entities` folder. Each chunk has a `Position` int array holding its chunk
+//! coordinates and an `Entities` list.
+
+use crate::region::Region;
+use crate::{get_variant, get_variant_mut, quarantine, Compound, NBTValue, ValueType};
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+
+/// Tags entities keep their items in besides the single `Item` of item frames and dropped items.
+/// `ArmorItems` and `HandItems` have a fixed length, so their entries are emptied instead of
+/// removed.
+const ITEM_TAGS: &[&str] = &["Items", "ArmorItems", "HandItems", "equipment"];
+
+#[derive(Debug, Clone)]
+pub struct EntityEntry {
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+    /// Position of the entry in the chunk's entity list
+    pub index: usize,
+    pub id: String,
+    pub uuid: Option<String>,
+    /// Value of the entity's `Pos` tag
+    pub pos: Option<[f64; 3]>,
+    pub size: usize,
+}
+
+/// Returns the chunk coordinates stored in an entity chunk's `Position` tag.
+pub fn position(chunk: &NBTValue) -> Result<Option<(i32, i32)>> {
+    let root = get_variant!(chunk.ty, ValueType::Compound);
+    Ok(match root.get("Position").map(|position| &position.ty) {
+        Some(ValueType::IntArray(position)) if position.len() == 2 => {
+            Some((position[0], position[1]))
+        }
+        _ => None,
+    })
+}
+
+pub fn entities(chunk: &NBTValue) -> Result<Option<&Vec<NBTValue>>> {
+    let root = get_variant!(chunk.ty, ValueType::Compound);
+    match root.get("Entities") {
+        Some(list) => Ok(Some(get_variant!(list.ty, ValueType::List))),
+        None => Ok(None),
+    }
+}
+
+pub fn entities_mut(chunk: &mut NBTValue) -> Result<Option<&mut Vec<NBTValue>>> {
+    let root = get_variant_mut!(chunk.ty, ValueType::Compound);
+    match root.get_mut("Entities") {
+        Some(list) => Ok(Some(get_variant_mut!(list.ty, ValueType::List))),
+        None => Ok(None),
+    }
+}
+
+fn entity_pos(entity: &Compound) -> Option<[f64; 3]> {
+    match &entity.get("Pos")?.ty {
+        ValueType::List(pos) if pos.len() == 3 => {
+            let mut coords = [0.0; 3];
+            for (coord, value) in coords.iter_mut().zip(pos) {
+                *coord = match value.ty {
+                    ValueType::Double(value) => value,
+                    _ => return None,
+                };
+            }
+            Some(coords)
+        }
+        _ => None,
+    }
+}
+
+/// Collects the entities of every chunk in an entity region, ranked from largest to smallest.
+pub fn rank_entities(region: &Region) -> Result<Vec<EntityEntry>> {
+    let mut entries = Vec::new();
+    for chunk in &region.chunks {
+        let list = match entities(&chunk.root)? {
+            Some(list) => list,
+            None => continue,
+        };
+        let (chunk_x, chunk_z) = position(&chunk.root)?.unwrap_or((chunk.x, chunk.z));
+        for (index, entry) in list.iter().enumerate() {
+            let compound = get_variant!(entry.ty, ValueType::Compound);
+            let id = match compound.get("id") {
+                Some(id) => get_variant!(id.ty, ValueType::String).clone(),
+                None => "unknown".to_string(),
+            };
+            entries.push(EntityEntry {
+                chunk_x,
+                chunk_z,
+                index,
+                id,
+                uuid: quarantine::uuid(entry),
+                pos: entity_pos(compound),
+                size: entry.size(),
+            });
+        }
+    }
+    entries.sort_by_key(|entry| Reverse(entry.size));
+    Ok(entries)
+}
+
+/// Finds the entity with the given UUID, returning its chunk's entity list and its position in
+/// it.
+fn find_entity<'a>(region: &'a mut Region, uuid: &str) -> Result<(&'a mut Vec<NBTValue>, usize)> {
+    let entry = rank_entities(region)?
+        .into_iter()
+        .find(|entry| entry.uuid.as_deref() == Some(uuid))
+        .with_context(|| format!("No entity with UUID {}", uuid))?;
+    let chunk = region
+        .chunks
+        .iter_mut()
+        .find(|chunk| {
+            let position = position(&chunk.root).ok().flatten();
+            position.unwrap_or((chunk.x, chunk.z)) == (entry.chunk_x, entry.chunk_z)
+        })
+        .unwrap();
+    Ok((entities_mut(&mut chunk.root)?.unwrap(), entry.index))
+}
+
+/// Removes the entity with the given UUID from its chunk.
+pub fn remove_entity(region: &mut Region, uuid: &str) -> Result<NBTValue> {
+    let (list, index) = find_entity(region, uuid)?;
+    Ok(list.remove(index))
+}
+
+/// Empties every item tag of the entity with the given UUID, returning the combined size of
+/// the items it held.
+pub fn clear_entity_items(region: &mut Region, uuid: &str) -> Result<usize> {
+    let (list, index) = find_entity(region, uuid)?;
+    let entity = &mut list[index];
+    let compound = get_variant_mut!(entity.ty, ValueType::Compound);
+    let mut removed_size = 0;
+    for &tag in ITEM_TAGS {
+        let value = match compound.get_mut(tag) {
+            Some(value) => value,
+            None => continue,
+        };
+        match &mut value.ty {
+            ValueType::List(items) if tag == "ArmorItems" || tag == "HandItems" => {
+                for item in items {
+                    removed_size += item.size();
+                    *item = NBTValue::new(ValueType::Compound(Compound::new()));
+                }
+            }
+            ValueType::List(items) => {
+                removed_size += items.iter().map(NBTValue::size).sum::<usize>();
+                items.clear();
+            }
+            ValueType::Compound(items) => {
+                removed_size += items.values().map(NBTValue::size).sum::<usize>();
+                items.clear();
+            }
+            _ => {}
+        }
+    }
+    if let Some(item) = compound.shift_remove("Item") {
+        removed_size += item.size();
+    }
+    Ok(removed_size)
+}
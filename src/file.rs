@@ -0,0 +1,379 @@
+use crate::NBTValue;
+use anyhow::{bail, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zlib,
+    None,
+}
+
+impl Compression {
+    /// Guesses the compression of NBT data from its first bytes.
+    pub fn detect(data: &[u8]) -> Result<Self> {
+        match data {
+            [0x1f, 0x8b, ..] => Ok(Compression::Gzip),
+            // The zlib header checksum makes the first two bytes a multiple of 31
+            [0x78, flags, ..] if (0x7800 | *flags as u16).is_multiple_of(31) => {
+                Ok(Compression::Zlib)
+            }
+            [0x0a, ..] => Ok(Compression::None),
+            _ => bail!("Data is not gzip, zlib or uncompressed NBT"),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zlib => "zlib",
+            Compression::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(Compression::Gzip),
+            "zlib" => Some(Compression::Zlib),
+            "none" => Some(Compression::None),
+            _ => None,
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            Compression::Gzip => GzDecoder::new(data).read_to_end(&mut decompressed)?,
+            Compression::Zlib => ZlibDecoder::new(data).read_to_end(&mut decompressed)?,
+            Compression::None => return Ok(data.to_vec()),
+        };
+        Ok(decompressed)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let level = flate2::Compression::new(9);
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::None => Ok(data.to_vec()),
+        }
+    }
+}
+
+/// The contents of an NBT file along with how it was stored.
+pub struct NBTFile {
+    /// Name of the root compound, `None` if it was stored without one like network NBT
+    pub name: Option<String>,
+    pub root: NBTValue,
+    pub compression: Compression,
+}
+
+impl NBTFile {
+    pub fn new(root: NBTValue) -> Self {
+        Self {
+            name: Some(String::new()),
+            root,
+            compression: Compression::Gzip,
+        }
+    }
+}
+
+pub struct WriteOptions {
+    /// How many timestamped `.bak` copies of the original file to keep, 0 disables backups
+    pub backups: usize,
+    /// Compression to use instead of the one the file was read with
+    pub compression: Option<Compression>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            backups: 5,
+            compression: None,
+        }
+    }
+}
+
+/// Reads an NBT file such as `player.dat`, detecting whether it is gzip compressed, zlib
+/// compressed or uncompressed.
+pub fn read_file(path: &Path) -> Result<NBTFile> {
+    let data = fs::read(path)?;
+    let compression = Compression::detect(&data)?;
+    let (name, root) = crate::from_bytes_named(compression.decompress(&data)?)?;
+    Ok(NBTFile {
+        name,
+        root,
+        compression,
+    })
+}
+
+/// Replaces `path` with the NBT file, see `write_atomic`.
+pub fn write_file(path: &Path, file: &NBTFile, options: &WriteOptions) -> Result<()> {
+    let compression = options.compression.unwrap_or(file.compression);
+    let data = compression.compress(&crate::to_bytes_named(file.name.as_deref(), &file.root)?)?;
+    write_atomic(path, &data, options)
+}
+
+/// Replaces `path` with `data`, backing up the original first. The data is written to a
+/// temporary file next to it and renamed over the original, so an interrupted write leaves the
+/// original untouched. The new file gets the original's permissions and, where allowed, owner.
+pub fn write_atomic(path: &Path, data: &[u8], options: &WriteOptions) -> Result<()> {
+    if options.backups > 0 && path.exists() {
+        backup(path, options.backups).context("Failed to back up the original file")?;
+    }
+
+    let original = fs::metadata(path).ok();
+    let temp_path = sibling_path(path, ".", ".tmp");
+    let result = write_synced(&temp_path, data, original.as_ref());
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.context("Failed to write the temporary file")?;
+    fs::rename(&temp_path, path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+/// Deletes `path`, backing it up first like `write_atomic` does.
+pub fn remove(path: &Path, options: &WriteOptions) -> Result<()> {
+    if options.backups > 0 {
+        backup(path, options.backups).context("Failed to back up the original file")?;
+    }
+    fs::remove_file(path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+/// Writes a new file, giving it the permissions and owner of `original` before any data goes in.
+fn write_synced(path: &Path, data: &[u8], original: Option<&Metadata>) -> Result<()> {
+    let mut file = File::create(path)?;
+    if let Some(original) = original {
+        copy_owner(&file, original);
+        file.set_permissions(original.permissions())?;
+    }
+    file.write_all(data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Gives the file the owner of `original`. Only root can hand files to other users, so this is
+/// skipped when it isn't allowed, which leaves the file to whoever is running the tool.
+#[cfg(unix)]
+fn copy_owner(file: &File, original: &Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    let _ = fchown(file, Some(original.uid()), Some(original.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _original: &Metadata) {}
+
+/// Makes the rename of a file durable by syncing the directory it is in.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Builds the path of a file next to `path`, named `<prefix><file name><suffix>`.
+fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Copies `path` to `<file name>.<unix time in ms>.bak` and deletes all but the newest `keep`
+/// backups of it.
+fn backup(path: &Path, keep: usize) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    fs::copy(path, sibling_path(path, "", &format!(".{}.bak", timestamp)))?;
+
+    let mut backups = backups(path)?;
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for (_, backup) in backups.into_iter().take(excess) {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+/// Lists the backups of `path` along with their timestamps.
+pub fn backups(path: &Path) -> Result<Vec<(u128, PathBuf)>> {
+    let name = path.file_name().context("Path has no file name")?;
+    let prefix = format!("{}.", name.to_string_lossy());
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let timestamp = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|name| name.strip_suffix(".bak"))
+            .and_then(|timestamp| timestamp.parse().ok());
+        if let Some(timestamp) = timestamp {
+            backups.push((timestamp, entry.path()));
+        }
+    }
+    Ok(backups)
+}
+
+/// Lists the player files in a world's `playerdata` folder. `dir` may be either the world
+/// folder or the `playerdata` folder itself.
+pub fn player_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let playerdata = dir.join("playerdata");
+    let dir = if playerdata.is_dir() {
+        &playerdata
+    } else {
+        dir
+    };
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension() == Some("dat".as_ref()) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes an empty directory for a test to put its files in.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("large_nbt_fixer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 8]).unwrap(),
+            Compression::Gzip
+        );
+        for &flags in &[0x01, 0x5e, 0x9c, 0xda] {
+            assert_eq!(
+                Compression::detect(&[0x78, flags]).unwrap(),
+                Compression::Zlib
+            );
+        }
+        assert_eq!(
+            Compression::detect(&[0x0a, 0, 0]).unwrap(),
+            Compression::None
+        );
+        // Fails the zlib header checksum
+        assert!(Compression::detect(&[0x78, 0x00]).is_err());
+        assert!(Compression::detect(&[0x08, 0x00]).is_err());
+        assert!(Compression::detect(&[]).is_err());
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let data = [0x0a, 0x00, 0x00, 0x00];
+        for &compression in &[Compression::Gzip, Compression::Zlib, Compression::None] {
+            let compressed = compression.compress(&data).unwrap();
+            assert_eq!(Compression::detect(&compressed).unwrap(), compression);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn backups_match_only_their_file() {
+        let dir = test_dir("backups");
+        let path = dir.join("p.dat");
+        for name in &[
+            "p.dat",
+            "p.dat.1000.bak",
+            "p.dat.20.bak",
+            "p.dat.bak",
+            "p.dat.x.bak",
+            "p.dat.1000.bak.tmp",
+            "pp.dat.5.bak",
+            "other.dat.7.bak",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let mut found = backups(&path).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                (20, dir.join("p.dat.20.bak")),
+                (1000, dir.join("p.dat.1000.bak"))
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backup_keeps_the_newest() {
+        let dir = test_dir("backup");
+        let path = dir.join("p.dat");
+        fs::write(&path, b"current").unwrap();
+        for timestamp in &[1000, 2000, 3000] {
+            fs::write(dir.join(format!("p.dat.{}.bak", timestamp)), b"old").unwrap();
+        }
+        backup(&path, 2).unwrap();
+
+        let mut kept = backups(&path).unwrap();
+        kept.sort();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].0, 3000);
+        assert_eq!(fs::read(&kept[1].1).unwrap(), b"current");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("permissions");
+        let path = dir.join("p.dat");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let options = WriteOptions {
+            backups: 0,
+            compression: None,
+        };
+        write_atomic(&path, b"new", &options).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
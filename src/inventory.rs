@@ -0,0 +1,255 @@
+use crate::{get_variant, get_variant_mut, NBTValue, NBTWriter, ValueType};
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+
+/// The item lists stored in a player's data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ItemList {
+    Inventory,
+    EnderItems,
+}
+
+impl ItemList {
+    pub const ALL: [ItemList; 2] = [ItemList::Inventory, ItemList::EnderItems];
+
+    pub fn tag_name(self) -> &'static str {
+        match self {
+            ItemList::Inventory => "Inventory",
+            ItemList::EnderItems => "EnderItems",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ItemEntry {
+    pub list: ItemList,
+    /// Position of the entry in its list
+    pub index: usize,
+    /// Value of the entry's `Slot` tag, if it has one
+    pub slot: Option<i8>,
+    pub size: usize,
+    pub id: String,
+    /// Stack size from `Count`, or `count` since 1.20.5
+    pub count: Option<i32>,
+}
+
+/// An entry that was taken out of an item list.
+pub struct RemovedItem {
+    pub entry: ItemEntry,
+    pub value: NBTValue,
+}
+
+/// A way of choosing which entries of a player's item lists to remove.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    /// The entry at this position in the list
+    Index(ItemList, usize),
+    /// The entry with this `Slot` tag in the list
+    Slot(ItemList, i8),
+    /// Every entry with this item id, the `minecraft:` namespace may be left out
+    Id(String),
+    /// The n largest entries
+    Largest(usize),
+    /// Every entry taking up more than this many bytes
+    LargerThan(usize),
+}
+
+impl Selector {
+    fn matches(&self, rank: usize, item: &ItemEntry) -> bool {
+        match self {
+            Selector::Index(list, index) => item.list == *list && item.index == *index,
+            Selector::Slot(list, slot) => item.list == *list && item.slot == Some(*slot),
+            Selector::Id(id) => {
+                item.id == *id || item.id.strip_prefix("minecraft:") == Some(id.as_str())
+            }
+            Selector::Largest(n) => rank < *n,
+            Selector::LargerThan(size) => item.size > *size,
+        }
+    }
+}
+
+/// Names the part of the player's inventory a `Slot` value refers to.
+pub fn slot_label(list: ItemList, slot: i8) -> &'static str {
+    if list == ItemList::EnderItems {
+        return "ender chest";
+    }
+    match slot {
+        0..=8 => "hotbar",
+        9..=35 => "main",
+        100 => "armor, feet",
+        101 => "armor, legs",
+        102 => "armor, chest",
+        103 => "armor, head",
+        -106 => "offhand",
+        _ => "unknown",
+    }
+}
+
+/// Returns the player compound of a player file, which for a level.dat is the singleplayer
+/// player stored in `Data.Player`.
+pub fn player_data(root: &NBTValue) -> &NBTValue {
+    level_player(root).unwrap_or(root)
+}
+
+pub fn player_data_mut(root: &mut NBTValue) -> &mut NBTValue {
+    if level_player(root).is_none() {
+        return root;
+    }
+    let compound = match &mut root.ty {
+        ValueType::Compound(compound) => compound,
+        _ => unreachable!(),
+    };
+    match &mut compound.get_mut("Data").unwrap().ty {
+        ValueType::Compound(data) => data.get_mut("Player").unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+fn level_player(root: &NBTValue) -> Option<&NBTValue> {
+    let data = match &root.ty {
+        ValueType::Compound(compound) => compound.get("Data")?,
+        _ => return None,
+    };
+    match &data.ty {
+        ValueType::Compound(data) => data.get("Player"),
+        _ => None,
+    }
+}
+
+/// Returns one of the item lists of a player's root compound, if the player has it.
+pub fn item_list(root: &NBTValue, list: ItemList) -> Result<Option<&NBTValue>> {
+    let compound = get_variant!(root.ty, ValueType::Compound);
+    Ok(compound.get(list.tag_name()))
+}
+
+pub fn item_list_mut(root: &mut NBTValue, list: ItemList) -> Result<Option<&mut NBTValue>> {
+    let compound = get_variant_mut!(root.ty, ValueType::Compound);
+    Ok(compound.get_mut(list.tag_name()))
+}
+
+/// The combined size in bytes of a player's item lists.
+pub fn total_size(root: &NBTValue) -> Result<usize> {
+    let mut size = 0;
+    for &list in &ItemList::ALL {
+        if let Some(value) = item_list(root, list)? {
+            size += value.size();
+        }
+    }
+    Ok(size)
+}
+
+/// The combined size in bytes the player's item lists take up once written out. Unlike
+/// `total_size` this reflects changes made since the player was read.
+pub fn written_size(root: &NBTValue) -> Result<usize> {
+    let mut size = 0;
+    for &list in &ItemList::ALL {
+        if let Some(value) = item_list(root, list)? {
+            size += NBTWriter::new().write_payload(value)?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Collects the entries of an item list, ranked from largest to smallest.
+pub fn rank_items(list: ItemList, items: &[NBTValue]) -> Result<Vec<ItemEntry>> {
+    let mut entries = Vec::with_capacity(items.len());
+    for (index, entry) in items.iter().enumerate() {
+        let item = get_variant!(entry.ty, ValueType::Compound);
+        let id = get_variant!(
+            item.get("id").context("Item has no id")?.ty,
+            ValueType::String
+        );
+        let slot = match item.get("Slot") {
+            Some(slot) => Some(*get_variant!(slot.ty, ValueType::Byte)),
+            None => None,
+        };
+        let count = match item.get("Count").or_else(|| item.get("count")) {
+            Some(NBTValue {
+                ty: ValueType::Byte(count),
+                ..
+            }) => Some(*count as i32),
+            Some(NBTValue {
+                ty: ValueType::Int(count),
+                ..
+            }) => Some(*count),
+            _ => None,
+        };
+        entries.push(ItemEntry {
+            list,
+            index,
+            slot,
+            size: entry.size(),
+            id: id.clone(),
+            count,
+        });
+    }
+    entries.sort_by_key(|item| Reverse(item.size));
+    Ok(entries)
+}
+
+/// Collects the entries of all of a player's item lists, ranked from largest to smallest.
+pub fn rank_player(root: &NBTValue) -> Result<Vec<ItemEntry>> {
+    let mut entries = Vec::new();
+    for &list in &ItemList::ALL {
+        if let Some(value) = item_list(root, list)? {
+            let items = get_variant!(value.ty, ValueType::List);
+            entries.extend(rank_items(list, items)?);
+        }
+    }
+    entries.sort_by_key(|item| Reverse(item.size));
+    Ok(entries)
+}
+
+/// Returns the ranked entries matched by any of the selectors, largest first.
+pub fn select<'a>(items: &'a [ItemEntry], selectors: &[Selector]) -> Vec<&'a ItemEntry> {
+    items
+        .iter()
+        .enumerate()
+        .filter(|(rank, item)| selectors.iter().any(|s| s.matches(*rank, item)))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Removes the entries at `indices` from the list, returning them in ascending index order.
+pub fn remove_items(items: &mut Vec<NBTValue>, indices: &[usize]) -> Vec<NBTValue> {
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+    let mut removed = Vec::with_capacity(indices.len());
+    for &index in indices.iter().rev() {
+        removed.push(items.remove(index));
+    }
+    removed.reverse();
+    removed
+}
+
+/// Removes every entry of the player's item lists matched by any of the selectors, largest
+/// first.
+pub fn remove_selected(root: &mut NBTValue, selectors: &[Selector]) -> Result<Vec<RemovedItem>> {
+    let selected: Vec<ItemEntry> = {
+        let ranked = rank_player(root)?;
+        select(&ranked, selectors).into_iter().cloned().collect()
+    };
+
+    let mut removed = Vec::with_capacity(selected.len());
+    for &list in &ItemList::ALL {
+        let mut entries: Vec<&ItemEntry> = selected.iter().filter(|e| e.list == list).collect();
+        if entries.is_empty() {
+            continue;
+        }
+        entries.sort_by_key(|entry| entry.index);
+        let indices: Vec<usize> = entries.iter().map(|entry| entry.index).collect();
+        // The list must exist since entries were found in it
+        let value = item_list_mut(root, list)?.unwrap();
+        let items = get_variant_mut!(value.ty, ValueType::List);
+        let values = remove_items(items, &indices);
+        for (entry, value) in entries.into_iter().zip(values) {
+            removed.push(RemovedItem {
+                entry: entry.clone(),
+                value,
+            });
+        }
+    }
+    removed.sort_by_key(|item| Reverse(item.entry.size));
+    Ok(removed)
+}
@@ -0,0 +1,227 @@
+//! A typed JSON representation of NBT that can be converted back without losing anything.
+//! Every value is an object holding its tag type and value, like `{"type":"byte","value":1}`.
+//! Compounds are objects of typed values in their original order, lists and arrays are JSON
+//! arrays, and longs are strings so JavaScript doesn't round them. Non-finite floats are the
+//! strings `NaN`, `Infinity` and `-Infinity`. Empty lists that were stored with an element type
+//! have it as `element_type`, like `{"type":"list","value":[],"element_type":"compound"}`.
+//!
+//! The JSON of a whole file also has the root `name`, `null` for the nameless root of network
+//! NBT, and the `compression` the file was stored with, so it can be written back unchanged.
+
+use crate::file::{Compression, NBTFile};
+use crate::{Compound, List, NBTValue, ValueType};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+
+const TYPE_NAMES: [&str; 12] = [
+    "byte",
+    "short",
+    "int",
+    "long",
+    "float",
+    "double",
+    "byte_array",
+    "string",
+    "list",
+    "compound",
+    "int_array",
+    "long_array",
+];
+
+fn float_to_json(value: f64) -> Value {
+    if value.is_nan() {
+        json!("NaN")
+    } else if value.is_infinite() {
+        json!(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        json!(value)
+    }
+}
+
+fn float_from_json(json: &Value) -> Result<f64> {
+    match json {
+        Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => bail!("Invalid float {:?}", s),
+        },
+        _ => json.as_f64().context("Expected a number"),
+    }
+}
+
+/// Converts a value to typed JSON.
+pub fn to_json(value: &NBTValue) -> Value {
+    let json = match &value.ty {
+        ValueType::Byte(v) => json!(v),
+        ValueType::Short(v) => json!(v),
+        ValueType::Int(v) => json!(v),
+        ValueType::Long(v) => json!(v.to_string()),
+        ValueType::Float(v) => float_to_json(*v as f64),
+        ValueType::Double(v) => float_to_json(*v),
+        ValueType::ByteArray(array) => json!(array),
+        ValueType::String(s) => json!(s),
+        ValueType::List(list) => Value::Array(list.iter().map(to_json).collect()),
+        ValueType::Compound(compound) => Value::Object(
+            compound
+                .iter()
+                .map(|(key, value)| (key.clone(), to_json(value)))
+                .collect(),
+        ),
+        ValueType::IntArray(array) => json!(array),
+        ValueType::LongArray(array) => {
+            Value::Array(array.iter().map(|v| json!(v.to_string())).collect())
+        }
+    };
+    let type_name = TYPE_NAMES[value.ty.type_id() as usize - 1];
+    match &value.ty {
+        ValueType::List(list) if list.is_empty() && list.element_type != 0 => json!({
+            "type": type_name,
+            "value": json,
+            "element_type": TYPE_NAMES[list.element_type as usize - 1],
+        }),
+        _ => json!({ "type": type_name, "value": json }),
+    }
+}
+
+fn integer<T: TryFrom<i64>>(json: &Value) -> Result<T> {
+    let value = json.as_i64().context("Expected an integer")?;
+    T::try_from(value)
+        .ok()
+        .with_context(|| format!("{} is out of range", value))
+}
+
+fn long(json: &Value) -> Result<i64> {
+    match json {
+        Value::String(s) => s.parse().with_context(|| format!("Invalid long {:?}", s)),
+        _ => json.as_i64().context("Expected a long"),
+    }
+}
+
+fn array<T>(json: &Value, element: impl Fn(&Value) -> Result<T>) -> Result<Vec<T>> {
+    let array = json.as_array().context("Expected an array")?;
+    array
+        .iter()
+        .enumerate()
+        .map(|(index, json)| element(json).with_context(|| format!("at [{}]", index)))
+        .collect()
+}
+
+fn object(json: &Value) -> Result<&Map<String, Value>> {
+    json.as_object().context("Expected an object")
+}
+
+/// Converts an NBT file to typed JSON, adding the root name and compression to the root.
+pub fn file_to_json(file: &NBTFile) -> Value {
+    let mut json = to_json(&file.root);
+    if let Value::Object(root) = &mut json {
+        root.insert("name".to_string(), json!(file.name));
+        root.insert("compression".to_string(), json!(file.compression.name()));
+    }
+    json
+}
+
+/// Converts the typed JSON of a file back to an NBT file. A missing root name or compression
+/// defaults to those of new files, an empty name and gzip.
+pub fn file_from_json(json: &Value) -> Result<NBTFile> {
+    let mut file = NBTFile::new(from_json(json)?);
+    let root = object(json)?;
+    match root.get("name") {
+        Some(Value::Null) => file.name = None,
+        Some(name) => file.name = Some(name.as_str().context("Expected a root name")?.into()),
+        None => {}
+    }
+    if let Some(compression) = root.get("compression") {
+        let name = compression.as_str().context("Expected a compression")?;
+        file.compression = Compression::from_name(name)
+            .with_context(|| format!("Unknown compression {:?}", name))?;
+    }
+    Ok(file)
+}
+
+/// Converts typed JSON back to a value.
+pub fn from_json(json: &Value) -> Result<NBTValue> {
+    let typed = object(json)?;
+    let type_name = typed
+        .get("type")
+        .and_then(Value::as_str)
+        .context("Missing tag type")?;
+    let json = typed.get("value").context("Missing value")?;
+    let ty = match type_name {
+        "byte" => ValueType::Byte(integer(json)?),
+        "short" => ValueType::Short(integer(json)?),
+        "int" => ValueType::Int(integer(json)?),
+        "long" => ValueType::Long(long(json)?),
+        "float" => ValueType::Float(float_from_json(json)? as f32),
+        "double" => ValueType::Double(float_from_json(json)?),
+        "byte_array" => ValueType::ByteArray(array(json, integer)?),
+        "string" => ValueType::String(json.as_str().context("Expected a string")?.to_string()),
+        "list" => {
+            let mut list = List::new(array(json, from_json)?);
+            if list.iter().any(|v| v.ty.type_id() != list.element_type) {
+                bail!("List elements must all have the same type");
+            }
+            if let Some(element_type) = typed.get("element_type") {
+                let element_type = element_type.as_str().context("Expected a tag type")?;
+                let position = TYPE_NAMES.iter().position(|name| *name == element_type);
+                let element_type = position.context("Unknown element type")? as u8 + 1;
+                if !list.is_empty() && element_type != list.element_type {
+                    bail!("List elements don't have the element type");
+                }
+                list.element_type = element_type;
+            }
+            ValueType::List(list)
+        }
+        "compound" => {
+            let mut compound = Compound::new();
+            for (key, json) in object(json)? {
+                let value = from_json(json).with_context(|| format!("at {:?}", key))?;
+                compound.insert(key.clone(), value);
+            }
+            ValueType::Compound(compound)
+        }
+        "int_array" => ValueType::IntArray(array(json, integer)?),
+        "long_array" => ValueType::LongArray(array(json, long)?),
+        _ => bail!("Unknown tag type {:?}", type_name),
+    };
+    Ok(NBTValue::new(ty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(name: Option<&str>, compression: Compression) {
+        // A root compound holding the byte `a`
+        let root = crate::from_bytes(vec![0x0a, 0x00, 0x00, 0x01, 0x00, 0x01, b'a', 5, 0x00]);
+        let file = NBTFile {
+            name: name.map(String::from),
+            root: root.unwrap(),
+            compression,
+        };
+        let json = file_to_json(&file);
+        let imported = file_from_json(&serde_json::from_str(&json.to_string()).unwrap()).unwrap();
+        assert_eq!(imported.name, file.name);
+        assert_eq!(imported.compression, compression);
+        assert_eq!(
+            crate::to_bytes_named(imported.name.as_deref(), &imported.root).unwrap(),
+            crate::to_bytes_named(name, &file.root).unwrap()
+        );
+    }
+
+    #[test]
+    fn file_round_trip() {
+        round_trip(Some(""), Compression::Gzip);
+        round_trip(Some("hello"), Compression::Zlib);
+        round_trip(None, Compression::None);
+    }
+
+    #[test]
+    fn file_defaults() {
+        let json = json!({"type": "compound", "value": {}});
+        let file = file_from_json(&json).unwrap();
+        assert_eq!(file.name.as_deref(), Some(""));
+        assert_eq!(file.compression, Compression::Gzip);
+    }
+}
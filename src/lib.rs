@@ -0,0 +1,279 @@
+//! Reading, writing and size analysis of Minecraft NBT data.
+
+pub mod container;
+pub mod entity;
+pub mod file;
+pub mod inventory;
+pub mod json;
+mod lz4;
+mod mutf8;
+pub mod path;
+pub mod prune;
+pub mod quarantine;
+mod reader;
+pub mod region;
+pub mod report;
+pub mod snbt;
+mod writer;
+
+pub use reader::NBTReader;
+pub use writer::NBTWriter;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+
+#[doc(hidden)]
+pub use anyhow::bail as __bail;
+
+/// Compounds keep their entries in the order they were read in, so unchanged data is written
+/// back byte for byte.
+pub type Compound = IndexMap<String, NBTValue>;
+
+/// The elements of a list tag along with the element type it was stored with, which empty lists
+/// keep so they are written back the same way. Derefs to the elements.
+#[derive(Debug, Default)]
+pub struct List {
+    /// Type id of the elements, 0 for an empty list without a type
+    pub element_type: u8,
+    pub items: Vec<NBTValue>,
+}
+
+impl List {
+    pub fn new(items: Vec<NBTValue>) -> Self {
+        Self {
+            element_type: items.first().map_or(0, |item| item.ty.type_id()),
+            items,
+        }
+    }
+}
+
+impl From<Vec<NBTValue>> for List {
+    fn from(items: Vec<NBTValue>) -> Self {
+        Self::new(items)
+    }
+}
+
+impl FromIterator<NBTValue> for List {
+    fn from_iter<I: IntoIterator<Item = NBTValue>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for List {
+    type Item = NBTValue;
+    type IntoIter = std::vec::IntoIter<NBTValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a NBTValue;
+    type IntoIter = std::slice::Iter<'a, NBTValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut List {
+    type Item = &'a mut NBTValue;
+    type IntoIter = std::slice::IterMut<'a, NBTValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter_mut()
+    }
+}
+
+impl Deref for List {
+    type Target = Vec<NBTValue>;
+
+    fn deref(&self) -> &Vec<NBTValue> {
+        &self.items
+    }
+}
+
+impl DerefMut for List {
+    fn deref_mut(&mut self) -> &mut Vec<NBTValue> {
+        &mut self.items
+    }
+}
+
+#[derive(Debug)]
+pub enum ValueType {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(List),
+    Compound(Compound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+/// A parsed value along with the byte range it was read from.
+#[derive(Debug)]
+pub struct NBTValue {
+    pub start: usize,
+    pub end: usize,
+    pub ty: ValueType,
+}
+
+impl ValueType {
+    pub fn type_id(&self) -> u8 {
+        match self {
+            ValueType::Byte(_) => 1,
+            ValueType::Short(_) => 2,
+            ValueType::Int(_) => 3,
+            ValueType::Long(_) => 4,
+            ValueType::Float(_) => 5,
+            ValueType::Double(_) => 6,
+            ValueType::ByteArray(_) => 7,
+            ValueType::String(_) => 8,
+            ValueType::List(_) => 9,
+            ValueType::Compound(_) => 10,
+            ValueType::IntArray(_) => 11,
+            ValueType::LongArray(_) => 12,
+        }
+    }
+}
+
+impl NBTValue {
+    pub fn new(ty: ValueType) -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            ty,
+        }
+    }
+
+    /// The number of bytes this value took up in the data it was read from.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[macro_export]
+macro_rules! get_variant {
+    ($expression:expr, $variant:path) => {
+        match &$expression {
+            $variant(x) => x,
+            _ => {
+                $crate::__bail!("incorrect variant")
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! get_variant_mut {
+    ($expression:expr, $variant:path) => {
+        match &mut $expression {
+            $variant(x) => x,
+            _ => {
+                $crate::__bail!("incorrect variant")
+            }
+        }
+    };
+}
+
+/// Parses uncompressed NBT data, returning the root compound.
+pub fn from_bytes(data: Vec<u8>) -> Result<NBTValue> {
+    Ok(from_bytes_named(data)?.1)
+}
+
+/// Parses uncompressed NBT data, returning the name of the root compound along with it. The name
+/// is `None` for network NBT, which has had a nameless root since 1.20.2.
+pub fn from_bytes_named(data: Vec<u8>) -> Result<(Option<String>, NBTValue)> {
+    NBTReader::new(data).read_root()
+}
+
+/// Serializes a root compound to uncompressed NBT data with an empty root name.
+pub fn to_bytes(root: &NBTValue) -> Result<Vec<u8>> {
+    to_bytes_named(Some(""), root)
+}
+
+/// Serializes a root compound to uncompressed NBT data, leaving out the root name if it is
+/// `None`.
+pub fn to_bytes_named(name: Option<&str>, root: &NBTValue) -> Result<Vec<u8>> {
+    NBTWriter::new().write_root(name, root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_list_keeps_element_type() {
+        // A root compound holding `L`, an empty list of compounds
+        let data = vec![
+            0x0a, 0x00, 0x00, 0x09, 0x00, 0x01, b'L', 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let root = from_bytes(data.clone()).unwrap();
+        assert_eq!(to_bytes(&root).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_list_keeps_element_type_through_json() {
+        let data = vec![
+            0x0a, 0x00, 0x00, 0x09, 0x00, 0x01, b'L', 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let root = from_bytes(data.clone()).unwrap();
+        let root = json::from_json(&json::to_json(&root)).unwrap();
+        assert_eq!(to_bytes(&root).unwrap(), data);
+    }
+
+    fn compound_len(root: &NBTValue) -> usize {
+        match &root.ty {
+            ValueType::Compound(compound) => compound.len(),
+            _ => panic!("root is not a compound"),
+        }
+    }
+
+    #[test]
+    fn named_root() {
+        let data = vec![
+            0x0a, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o', 0x01, 0x00, 0x01, b'a', 5, 0x00,
+        ];
+        let (name, root) = from_bytes_named(data.clone()).unwrap();
+        assert_eq!(name.as_deref(), Some("hello"));
+        assert_eq!(compound_len(&root), 1);
+        assert_eq!(to_bytes_named(name.as_deref(), &root).unwrap(), data);
+    }
+
+    #[test]
+    fn nameless_root() {
+        let data = vec![0x0a, 0x01, 0x00, 0x01, b'a', 5, 0x00];
+        let (name, root) = from_bytes_named(data.clone()).unwrap();
+        assert_eq!(name, None);
+        assert_eq!(compound_len(&root), 1);
+        assert_eq!(to_bytes_named(None, &root).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_nameless_root() {
+        let (name, root) = from_bytes_named(vec![0x0a, 0x00]).unwrap();
+        assert_eq!(name, None);
+        assert_eq!(compound_len(&root), 0);
+        assert_eq!(to_bytes_named(None, &root).unwrap(), [0x0a, 0x00]);
+    }
+
+    #[test]
+    fn trailing_data_is_an_error() {
+        let err = from_bytes_named(vec![0x0a, 0x00, 0x00, 0x00, 0xff]).unwrap_err();
+        assert!(err.to_string().contains("trailing data"), "{}", err);
+    }
+
+    #[test]
+    fn root_must_be_a_compound() {
+        assert!(from_bytes_named(vec![0x01, 0x00, 0x00, 5]).is_err());
+        assert!(from_bytes_named(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! The framing of lz4-java's `LZ4BlockOutputStream`, which Minecraft uses for LZ4 compressed
+//! chunks. Each block has a header of the magic bytes, a token holding the compression method
+//! and level, the compressed and decompressed lengths and a checksum of the decompressed data.
+//! The stream ends with an empty block.
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+
+const MAGIC: &[u8] = b"LZ4Block";
+const METHOD_RAW: u8 = 0x10;
+const METHOD_LZ4: u8 = 0x20;
+const BLOCK_SIZE: usize = 1 << 16;
+/// lz4-java derives the level stored in the token from the block size
+const COMPRESSION_LEVEL: u8 = 6;
+const CHECKSUM_SEED: u32 = 0x9747b28c;
+
+fn checksum(data: &[u8]) -> u32 {
+    xxhash32(data, CHECKSUM_SEED) & 0x0fff_ffff
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::new(data);
+    let mut decompressed = Vec::new();
+    loop {
+        let mut magic = [0; 8];
+        cursor.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            bail!("Invalid LZ4 block magic");
+        }
+        let token = cursor.read_u8()?;
+        let compressed_len = cursor.read_i32::<LittleEndian>()?;
+        let decompressed_len = cursor.read_i32::<LittleEndian>()?;
+        let expected_checksum = cursor.read_u32::<LittleEndian>()?;
+        if decompressed_len == 0 {
+            return Ok(decompressed);
+        }
+        // The low bits of the token give the block size the stream was written with
+        let max_block_size = 1 << (10 + (token & 0x0f));
+        let remaining = data.len() - cursor.position() as usize;
+        if compressed_len < 0 || compressed_len as usize > remaining {
+            bail!("Invalid LZ4 block length {}", compressed_len);
+        }
+        if decompressed_len < 0 || decompressed_len > max_block_size {
+            bail!("Invalid LZ4 decompressed block length {}", decompressed_len);
+        }
+        let (compressed_len, decompressed_len) =
+            (compressed_len as usize, decompressed_len as usize);
+
+        let mut block = vec![0; compressed_len];
+        cursor.read_exact(&mut block)?;
+        let block = match token & 0xf0 {
+            METHOD_RAW => block,
+            METHOD_LZ4 => lz4_flex::block::decompress(&block, decompressed_len)?,
+            method => bail!("Unknown LZ4 block compression method {:#x}", method),
+        };
+        if checksum(&block) != expected_checksum {
+            bail!("LZ4 block checksum mismatch");
+        }
+        decompressed.extend_from_slice(&block);
+    }
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    for block in data.chunks(BLOCK_SIZE) {
+        let lz4 = lz4_flex::block::compress(block);
+        // Incompressible blocks are stored as is, like lz4-java does
+        let (method, body) = if lz4.len() < block.len() {
+            (METHOD_LZ4, &lz4[..])
+        } else {
+            (METHOD_RAW, block)
+        };
+        compressed.extend_from_slice(MAGIC);
+        compressed.write_u8(method | COMPRESSION_LEVEL)?;
+        compressed.write_i32::<LittleEndian>(body.len() as i32)?;
+        compressed.write_i32::<LittleEndian>(block.len() as i32)?;
+        compressed.write_u32::<LittleEndian>(checksum(block))?;
+        compressed.extend_from_slice(body);
+    }
+    compressed.extend_from_slice(MAGIC);
+    compressed.write_u8(METHOD_RAW | COMPRESSION_LEVEL)?;
+    compressed.write_i32::<LittleEndian>(0)?;
+    compressed.write_i32::<LittleEndian>(0)?;
+    compressed.write_u32::<LittleEndian>(0)?;
+    Ok(compressed)
+}
+
+fn xxhash32(data: &[u8], seed: u32) -> u32 {
+    const PRIME1: u32 = 2654435761;
+    const PRIME2: u32 = 2246822519;
+    const PRIME3: u32 = 3266489917;
+    const PRIME4: u32 = 668265263;
+    const PRIME5: u32 = 374761393;
+
+    fn round(acc: u32, input: u32) -> u32 {
+        acc.wrapping_add(input.wrapping_mul(PRIME2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME1)
+    }
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    let mut stripes = data.chunks_exact(16);
+    let mut hash = if data.len() >= 16 {
+        let mut v = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+        for stripe in &mut stripes {
+            for (i, acc) in v.iter_mut().enumerate() {
+                *acc = round(*acc, read_u32(&stripe[i * 4..]));
+            }
+        }
+        v[0].rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME5)
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+
+    let mut words = stripes.remainder().chunks_exact(4);
+    for word in &mut words {
+        hash = hash.wrapping_add(read_u32(word).wrapping_mul(PRIME3));
+        hash = hash.rotate_left(17).wrapping_mul(PRIME4);
+    }
+    for &byte in words.remainder() {
+        hash = hash.wrapping_add((byte as u32).wrapping_mul(PRIME5));
+        hash = hash.rotate_left(11).wrapping_mul(PRIME1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xxhash32_reference() {
+        assert_eq!(xxhash32(b"", 0), 0x02cc5d05);
+        assert_eq!(xxhash32(b"abc", 0), 0x32d153ff);
+        assert_eq!(
+            xxhash32(b"Nobody inspects the spammish repetition", 0),
+            0xe2293b2f
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let compressible: Vec<u8> = (0..200_000).map(|i| (i % 7) as u8).collect();
+        // A simple generator so the data doesn't compress
+        let mut state = 1u32;
+        let incompressible: Vec<u8> = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        for data in &[
+            Vec::new(),
+            b"minecraft".to_vec(),
+            compressible,
+            incompressible,
+        ] {
+            assert_eq!(decompress(&compress(data).unwrap()).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        for &(compressed_len, decompressed_len) in &[(-1, 16), (1000, 16), (4, -1), (4, 1 << 30)] {
+            let mut data = MAGIC.to_vec();
+            data.write_u8(METHOD_RAW | COMPRESSION_LEVEL).unwrap();
+            data.write_i32::<LittleEndian>(compressed_len).unwrap();
+            data.write_i32::<LittleEndian>(decompressed_len).unwrap();
+            data.write_u32::<LittleEndian>(0).unwrap();
+            data.extend_from_slice(&[0; 4]);
+            assert!(decompress(&data).is_err());
+        }
+    }
+}
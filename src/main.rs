@@ -1,274 +1,1128 @@
 use anyhow::{bail, Context, Result};
-use byteorder::{BigEndian, ReadBytesExt};
-use clap::clap_app;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Cursor, Read, Write};
-use std::path::Path;
-
-#[derive(Debug)]
-enum ValueType {
-    Byte(i8),
-    Short(i16),
-    Int(i32),
-    Long(i64),
-    Float(f32),
-    Double(f64),
-    ByteArray(Vec<i8>),
-    String(String),
-    List(Vec<NBTValue>),
-    Compound(HashMap<String, NBTValue>),
-    IntArray(Vec<i32>),
-    LongArray(Vec<i64>),
-}
-
-#[derive(Debug)]
-struct NBTValue {
-    start: usize,
-    end: usize,
-    ty: ValueType,
-}
-
-impl NBTValue {
-    fn size(&self) -> usize {
-        self.end - self.start
-    }
-}
-
-struct NBTReader {
-    buffer: Cursor<Vec<u8>>,
-}
-
-impl NBTReader {
-    const READ_FNS: &'static [fn(&mut Self) -> Result<ValueType>] = &[
-        Self::read_zero,
-        Self::read_byte,
-        Self::read_short,
-        Self::read_int,
-        Self::read_long,
-        Self::read_float,
-        Self::read_double,
-        Self::read_byte_array,
-        Self::read_string,
-        Self::read_list,
-        Self::read_compound,
-        Self::read_int_array,
-        Self::read_long_array,
-    ];
-
-    fn new(data: Vec<u8>) -> Self {
-        Self {
-            buffer: Cursor::new(data),
-        }
-    }
+use clap::{clap_app, App, AppSettings, Arg, ArgMatches};
+use large_nbt_fixer::container;
+use large_nbt_fixer::entity;
+use large_nbt_fixer::file::{self, Compression, NBTFile, WriteOptions};
+use large_nbt_fixer::inventory::{self, ItemEntry, ItemList, Selector};
+use large_nbt_fixer::json;
+use large_nbt_fixer::path::{self, NBTPath};
+use large_nbt_fixer::prune::{self, NestedLocation};
+use large_nbt_fixer::quarantine;
+use large_nbt_fixer::region::{self, Region};
+use large_nbt_fixer::report;
+use large_nbt_fixer::snbt;
+use large_nbt_fixer::{get_variant, NBTValue, ValueType};
+use std::cmp::Reverse;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-    fn read(&mut self) -> Result<NBTValue> {
-        self.read_value(10)
+fn get_input() -> Result<String> {
+    let mut buffer = String::new();
+    std::io::stdin().read_line(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let answer = get_input()?.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Formats a list position or slot the way it is given on the command line, with ender chest
+/// entries prefixed by `ender:`.
+fn location<T: std::fmt::Display>(list: ItemList, n: T) -> String {
+    match list {
+        ItemList::Inventory => n.to_string(),
+        ItemList::EnderItems => format!("ender:{}", n),
     }
+}
 
-    fn read_value(&mut self, type_id: usize) -> Result<NBTValue> {
-        let reader = Self::READ_FNS[type_id];
-        let start = self.buffer.position() as usize;
-        let inner = reader(self)?;
-        let end = self.buffer.position() as usize;
-        Ok(NBTValue {
-            start,
-            end,
-            ty: inner,
-        })
+fn parse_location<T: FromStr>(s: &str) -> Result<(ItemList, T)>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let (list, n) = match s.strip_prefix("ender:") {
+        Some(n) => (ItemList::EnderItems, n),
+        None => (ItemList::Inventory, s),
+    };
+    Ok((list, n.parse()?))
+}
+
+fn describe(item: &ItemEntry) -> String {
+    match item.slot {
+        Some(slot) => format!(
+            "Slot {} ({}): {} bytes ({})",
+            location(item.list, slot),
+            inventory::slot_label(item.list, slot),
+            item.size,
+            item.id
+        ),
+        None => format!(
+            "Entry {}: {} bytes ({})",
+            location(item.list, item.index),
+            item.size,
+            item.id
+        ),
     }
+}
 
-    fn read_zero(&mut self) -> Result<ValueType> {
-        unreachable!("Tried to read value with type id 0");
+/// Parses an item inside the container in a player's slot, given as
+/// `<slot>/<index>[/<index>...]`.
+fn parse_nested(s: &str) -> Result<NestedLocation> {
+    let mut parts = s.split('/');
+    let (list, slot) = parse_location(parts.next().unwrap())?;
+    let path = parts
+        .map(|index| index.parse())
+        .collect::<Result<Vec<usize>, _>>()?;
+    if path.is_empty() {
+        bail!("Expected a nested item path after the slot");
     }
+    Ok(NestedLocation { list, slot, path })
+}
 
-    fn read_byte(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Byte(self.buffer.read_i8()?))
+fn nested_location(list: ItemList, slot: i8, path: &[usize]) -> String {
+    let mut location = location(list, slot);
+    for index in path {
+        location.push_str(&format!("/{}", index));
     }
+    location
+}
 
-    fn read_short(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Short(self.buffer.read_i16::<BigEndian>()?))
+fn print_nested(nbt: &NBTValue, item: &ItemEntry) -> Result<()> {
+    let slot = match item.slot {
+        Some(slot) => slot,
+        None => return Ok(()),
+    };
+    let list = inventory::item_list(nbt, item.list)?.context("Item list missing")?;
+    let value = &get_variant!(list.ty, ValueType::List)[item.index];
+    for entry in container::rank_nested(value)? {
+        println!(
+            "    Nested {}: {} bytes ({})",
+            nested_location(item.list, slot, &entry.path),
+            entry.size,
+            entry.id
+        );
     }
+    Ok(())
+}
 
-    fn read_int(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Int(self.buffer.read_i32::<BigEndian>()?))
+fn write_options(matches: &ArgMatches) -> Result<WriteOptions> {
+    let mut options = WriteOptions::default();
+    if let Some(backups) = matches.value_of("backups") {
+        options.backups = backups.parse().context("Invalid number of backups")?;
     }
+    options.compression = matches
+        .value_of("compression")
+        .and_then(Compression::from_name);
+    Ok(options)
+}
 
-    fn read_long(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Long(self.buffer.read_i64::<BigEndian>()?))
+fn parse_selectors(matches: &ArgMatches) -> Result<Vec<Selector>> {
+    let mut selectors = Vec::new();
+    for index in matches.values_of("index").into_iter().flatten() {
+        let (list, index) = parse_location(index).context("Invalid index")?;
+        selectors.push(Selector::Index(list, index));
+    }
+    for slot in matches.values_of("slot").into_iter().flatten() {
+        let (list, slot) = parse_location(slot).context("Invalid slot")?;
+        selectors.push(Selector::Slot(list, slot));
+    }
+    for id in matches.values_of("id").into_iter().flatten() {
+        selectors.push(Selector::Id(id.to_string()));
+    }
+    if let Some(n) = matches.value_of("largest") {
+        let n = n.parse().context("Invalid number of items")?;
+        selectors.push(Selector::Largest(n));
     }
+    if let Some(size) = matches.value_of("max_size") {
+        let size = size.parse().context("Invalid size threshold")?;
+        selectors.push(Selector::LargerThan(size));
+    }
+    Ok(selectors)
+}
 
-    fn read_float(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Float(self.buffer.read_f32::<BigEndian>()?))
+/// Prints the ranked items of one or more player files as JSON or CSV. Files that can't be read
+/// are reported on stderr.
+fn print_report(files: &[PathBuf], format: &str) -> Result<()> {
+    let rankings = report::ranking(files);
+    for (path, ranking) in &rankings {
+        if let Err(err) = ranking {
+            eprintln!("{}: failed to read: {:#}", path.display(), err);
+        }
+    }
+    if format == "csv" {
+        print!("{}", report::to_csv(&rankings));
+    } else {
+        let report = report::to_json(&rankings);
+        println!("{}", serde_json::to_string_pretty(&report)?);
     }
+    Ok(())
+}
 
-    fn read_double(&mut self) -> Result<ValueType> {
-        Ok(ValueType::Double(self.buffer.read_f64::<BigEndian>()?))
+fn fix_directory(dir: &Path, matches: &ArgMatches, selectors: &[Selector]) -> Result<()> {
+    let files = file::player_files(dir)?;
+    if files.is_empty() {
+        bail!("No player files found in {}", dir.display());
     }
 
-    fn read_byte_array(&mut self) -> Result<ValueType> {
-        let length = self.buffer.read_i32::<BigEndian>()?;
-        let mut items = Vec::new();
-        for _ in 0..length {
-            items.push(self.buffer.read_i8()?);
+    let mut rankings = Vec::with_capacity(files.len());
+    let mut errors = Vec::new();
+    for (path, ranking) in report::ranking(&files) {
+        match ranking {
+            Ok(ranking) => rankings.push((path, ranking)),
+            Err(err) => errors.push((path, err)),
         }
-        Ok(ValueType::ByteArray(items))
     }
+    rankings.sort_by_key(|(_, ranking)| Reverse(ranking.total_size));
 
-    fn read_string(&mut self) -> Result<ValueType> {
-        let length = self.buffer.read_i16::<BigEndian>()?;
-        let mut bytes = vec![0; length as usize];
-        self.buffer.read_exact(&mut bytes)?;
-        let string = String::from_utf8(bytes)?;
-        Ok(ValueType::String(string))
+    let top = match matches.value_of("top") {
+        Some(top) => top.parse().context("Invalid number of players")?,
+        None => 10,
+    };
+    println!("Scanned {} player files", files.len());
+    println!("Largest inventories and ender chests:");
+    for (path, ranking) in rankings.iter().take(top) {
+        let name = path.file_name().unwrap().to_string_lossy();
+        print!(
+            "{}: {} bytes, {} items",
+            name,
+            ranking.total_size,
+            ranking.items.len()
+        );
+        match ranking.items.first() {
+            Some(largest) => println!(", largest is {}", describe(largest)),
+            None => println!(),
+        }
+    }
+    for (path, err) in &errors {
+        let name = path.file_name().unwrap().to_string_lossy();
+        eprintln!("{}: failed to read: {:#}", name, err);
     }
 
-    fn read_name(&mut self) -> Result<String> {
-        let length = self.buffer.read_i16::<BigEndian>()?;
-        let mut bytes = vec![0; length as usize];
-        self.buffer.read_exact(&mut bytes)?;
-        Ok(String::from_utf8(bytes)?)
+    if selectors.is_empty() {
+        return Ok(());
+    }
+    if !matches.is_present("yes") && !confirm("Delete the selected items from every player?")? {
+        println!("Aborted");
+        return Ok(());
     }
 
-    fn read_list(&mut self) -> Result<ValueType> {
-        let type_id = self.buffer.read_i8()? as usize;
-        let length = self.buffer.read_i32::<BigEndian>()?;
-        let mut items = Vec::new();
-        for _ in 0..length {
-            items.push(self.read_value(type_id)?);
+    let options = write_options(matches)?;
+    let quarantine_path = matches.value_of("quarantine").map(Path::new);
+    let mut total = 0;
+    let mut failed = 0;
+    for (path, _) in &rankings {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let pruned = file::read_file(path).and_then(|mut nbt_file| {
+            prune::prune_file(
+                path,
+                &mut nbt_file,
+                selectors,
+                &[],
+                &options,
+                quarantine_path,
+            )
+        });
+        match pruned {
+            Ok(pruned) if pruned.is_empty() => {}
+            Ok(pruned) => {
+                println!(
+                    "{}: dropped {} items totalling {} bytes",
+                    name,
+                    pruned.len(),
+                    pruned.size()
+                );
+                total += pruned.size();
+            }
+            Err(err) => {
+                eprintln!("{}: failed to remove items: {:#}", name, err);
+                failed += 1;
+            }
         }
-        Ok(ValueType::List(items))
     }
+    println!("Done! Removed {} bytes in total", total);
+    if failed > 0 {
+        eprintln!("{} files could not be modified", failed);
+    }
+    Ok(())
+}
+
+fn fix_file(path: &Path, matches: &ArgMatches, selectors: &[Selector]) -> Result<()> {
+    let mut nbt_file = file::read_file(path)?;
+    let nbt = inventory::player_data(&nbt_file.root);
+    let size = inventory::total_size(nbt)?;
+    let mut nested = Vec::new();
+    for spec in matches.values_of("remove_nested").into_iter().flatten() {
+        nested.push(parse_nested(spec).context("Invalid nested item")?);
+    }
+
+    let selectors = {
+        let items = inventory::rank_player(nbt)?;
+        if items.is_empty() {
+            bail!("Inventory and ender chest are empty");
+        }
 
-    fn read_compound(&mut self) -> Result<ValueType> {
-        let mut compound = HashMap::new();
-        loop {
-            let type_id = self.buffer.read_i8().unwrap() as usize;
-            if type_id == 0 {
-                return Ok(ValueType::Compound(compound));
+        println!("Total inventory and ender chest size is {} bytes", size);
+        println!("All items ranked by size:");
+        for item in &items {
+            println!("{}", describe(item));
+            if matches.is_present("nested") {
+                print_nested(nbt, item)?;
             }
-            let name = self.read_name()?;
-            compound.insert(name, self.read_value(type_id)?);
         }
-    }
 
-    fn read_int_array(&mut self) -> Result<ValueType> {
-        let length = self.buffer.read_i32::<BigEndian>()?;
-        let mut items = Vec::new();
-        for _ in 0..length {
-            items.push(self.buffer.read_i32::<BigEndian>()?);
+        if selectors.is_empty() && nested.is_empty() {
+            print!("Which slot would you like to delete? ");
+            std::io::stdout().flush()?;
+            let input = get_input()?;
+            let input = input.trim();
+            if input.contains('/') {
+                nested.push(parse_nested(input)?);
+                Vec::new()
+            } else {
+                let (list, slot) = parse_location(input)?;
+                let selectors = vec![Selector::Slot(list, slot)];
+                if inventory::select(&items, &selectors).is_empty() {
+                    bail!("Slot not found");
+                }
+                selectors
+            }
+        } else {
+            let selected = inventory::select(&items, selectors);
+            if selected.is_empty() && nested.is_empty() {
+                println!("No items matched, nothing to do");
+                return Ok(());
+            }
+            println!("Selected items:");
+            for item in &selected {
+                println!("{}", describe(item));
+            }
+            for location in &nested {
+                println!(
+                    "Nested {}",
+                    nested_location(location.list, location.slot, &location.path)
+                );
+            }
+            if !matches.is_present("yes") && !confirm("Delete the selected items?")? {
+                println!("Aborted");
+                return Ok(());
+            }
+            selectors.to_vec()
         }
-        Ok(ValueType::IntArray(items))
-    }
+    };
+
+    println!("Deleting...");
+    let options = write_options(matches)?;
+    let quarantine_path = matches.value_of("quarantine").map(Path::new);
+    let pruned = prune::prune_file(
+        path,
+        &mut nbt_file,
+        &selectors,
+        &nested,
+        &options,
+        quarantine_path,
+    )?;
 
-    fn read_long_array(&mut self) -> Result<ValueType> {
-        let length = self.buffer.read_i32::<BigEndian>()?;
-        let mut items = Vec::new();
-        for _ in 0..length {
-            items.push(self.buffer.read_i64::<BigEndian>()?);
+    println!(
+        "Done! New inventory and ender chest size is {} bytes",
+        inventory::written_size(inventory::player_data(&nbt_file.root))?
+    );
+    if !pruned.is_empty() {
+        println!(
+            "Dropped {} item{} totalling {} bytes:",
+            pruned.len(),
+            if pruned.len() == 1 { "" } else { "s" },
+            pruned.size()
+        );
+        for item in &pruned.items {
+            println!("{}", describe(item));
+        }
+        for item in &pruned.nested {
+            let location = &item.location;
+            println!(
+                "Nested {}: {} bytes ({})",
+                nested_location(location.list, location.slot, &location.path),
+                item.size,
+                item.id
+            );
         }
-        Ok(ValueType::LongArray(items))
     }
+    Ok(())
 }
 
-macro_rules! get_variant {
-    ($expression:expr, $variant:path) => {
-        match &$expression {
-            $variant(x) => x,
-            _ => {
-                bail!("incorrect variant")
+fn restore(matches: &ArgMatches) -> Result<()> {
+    let quarantine_path = Path::new(
+        matches
+            .value_of("quarantine")
+            .context("quarantine arg missing")?,
+    );
+    let mut entries = quarantine::load(quarantine_path)?;
+
+    let player_path = match matches.value_of("player") {
+        Some(player) => Path::new(player),
+        None => {
+            if entries.is_empty() {
+                println!("The quarantine is empty");
             }
+            for (n, entry) in entries.iter().enumerate() {
+                let location = match (entry.slot, &entry.nested) {
+                    (Some(slot), Some(path)) => {
+                        format!("nested {}", nested_location(entry.list, slot, path))
+                    }
+                    (Some(slot), None) => format!("slot {}", location(entry.list, slot)),
+                    (None, _) => entry.list.tag_name().to_string(),
+                };
+                println!(
+                    "{}: {} bytes ({}) from {} {}, removed at {}",
+                    n,
+                    entry.item.size(),
+                    container::item_id(&entry.item).unwrap_or("unknown"),
+                    entry.player,
+                    location,
+                    entry.removed_at
+                );
+            }
+            return Ok(());
         }
     };
+
+    let n: usize = matches
+        .value_of("entry")
+        .context("Pick the quarantined item to restore with --entry")?
+        .parse()
+        .context("Invalid entry")?;
+    if n >= entries.len() {
+        bail!("Quarantine only has {} items", entries.len());
+    }
+    let entry = entries.remove(n);
+    let list = match matches.value_of("into") {
+        Some("ender") => ItemList::EnderItems,
+        Some(_) => ItemList::Inventory,
+        None => entry.list,
+    };
+    let slot = match matches.value_of("slot") {
+        Some(slot) => Some(slot.parse().context("Invalid slot")?),
+        None => None,
+    };
+
+    let mut nbt_file = file::read_file(player_path)?;
+    let nbt = inventory::player_data_mut(&mut nbt_file.root);
+    let player = prune::player_name(nbt, player_path);
+    if player != entry.player {
+        println!(
+            "Warning: the item was taken from {}, not {}",
+            entry.player, player
+        );
+    }
+    quarantine::restore(nbt, entry, list, slot)?;
+
+    let options = write_options(matches)?;
+    file::write_file(player_path, &nbt_file, &options)?;
+    if !matches.is_present("keep") {
+        quarantine::save(quarantine_path, entries, &options)?;
+    }
+    println!("Done! Restored the item to {}", player_path.display());
+    Ok(())
 }
 
-fn get_input() -> Result<String> {
-    let mut buffer = String::new();
-    std::io::stdin().read_line(&mut buffer)?;
-    Ok(buffer)
+/// Parses comma separated coordinates such as `<x>,<y>,<z>`.
+fn parse_coords(s: &str, count: usize) -> Result<Vec<i32>> {
+    let coords = s
+        .split(',')
+        .map(|coord| coord.trim().parse())
+        .collect::<Result<Vec<i32>, _>>()
+        .with_context(|| format!("Invalid coordinates {}", s))?;
+    if coords.len() != count {
+        bail!("Expected {} coordinates but got {}", count, coords.len());
+    }
+    Ok(coords)
 }
 
-struct ItemEntry<'a> {
-    index: usize,
-    size: usize,
-    start: usize,
-    end: usize,
-    id: &'a str,
+fn print_chunk(region: &Region, x: i32, z: i32) -> Result<()> {
+    let chunk = region
+        .chunk(x, z)
+        .with_context(|| format!("Chunk {},{} is not in the region", x, z))?;
+    println!("Chunk {},{} is {} bytes", x, z, chunk.root.size());
+    println!("Block entities ranked by size:");
+    for entry in region::rank_block_entities(&chunk.root)? {
+        println!(
+            "{} at {},{},{}: {} bytes",
+            entry.id, entry.x, entry.y, entry.z, entry.size
+        );
+        let block_entity = &region::block_entities(&chunk.root)?.unwrap()[entry.index];
+        let compound = get_variant!(block_entity.ty, ValueType::Compound);
+        let items = match compound.get("Items") {
+            Some(items) => get_variant!(items.ty, ValueType::List),
+            None => continue,
+        };
+        let mut items: Vec<&NBTValue> = items.iter().collect();
+        items.sort_by_key(|item| Reverse(item.size()));
+        for item in items {
+            let compound = get_variant!(item.ty, ValueType::Compound);
+            let slot = match compound.get("Slot") {
+                Some(slot) => *get_variant!(slot.ty, ValueType::Byte),
+                None => continue,
+            };
+            println!(
+                "    Slot {}/{}: {} bytes ({})",
+                format_args!("{},{},{}", entry.x, entry.y, entry.z),
+                slot,
+                item.size(),
+                container::item_id(item).unwrap_or("unknown")
+            );
+        }
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let matches = clap_app!(large_nbt_fixer =>
-        (version: "1.0")
-        (author: "StackDoubleFlow <ojaslandge@gmail.com>")
-        (about: "Removes large nbt from player.dat files")
-        (@arg input: +required "The player.dat file to modify")
-    )
-    .get_matches();
+fn print_corrupt(region: &Region) {
+    for chunk in &region.corrupt {
+        println!(
+            "Chunk {},{} could not be read and is kept as it is: {:#}",
+            chunk.x, chunk.z, chunk.error
+        );
+    }
+}
 
-    let path = Path::new(matches.value_of("input").context("input arg missing")?);
-    let file = File::open(path)?;
-    let mut data = Vec::new();
-    GzDecoder::new(file).read_to_end(&mut data)?;
-
-    // The root compound doesn't have an end tag?
-    data.push(0);
-
-    let nbt = NBTReader::new(data.clone()).read()?;
-    let root = get_variant!(nbt.ty, ValueType::Compound);
-    let compound = get_variant!(root[""].ty, ValueType::Compound);
-    let inventory_val = &compound["Inventory"];
-    let inventory = get_variant!(inventory_val.ty, ValueType::List);
-
-    let mut items = Vec::with_capacity(inventory.len());
-    for (index, entry) in inventory.iter().enumerate() {
-        let item = get_variant!(entry.ty, ValueType::Compound);
-        let id = get_variant!(item["id"].ty, ValueType::String);
-        items.push(ItemEntry {
-            index,
-            size: entry.size(),
-            start: entry.start,
-            end: entry.end,
-            id,
-        });
+fn fix_region(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let mut region = Region::read(path)?;
+    let top = match matches.value_of("top") {
+        Some(top) => top.parse().context("Invalid number of entries")?,
+        None => 10,
+    };
+
+    if let Some(chunk) = matches.value_of("chunk") {
+        let coords = parse_coords(chunk, 2)?;
+        return print_chunk(&region, coords[0], coords[1]);
     }
-    items.sort_by_key(|item| item.size);
-    items.reverse();
 
-    if items.is_empty() {
-        bail!("Inventory is empty");
+    let mut chunks: Vec<_> = region.chunks.iter().collect();
+    chunks.sort_by_key(|chunk| Reverse(chunk.root.size()));
+    println!(
+        "Region {},{} has {} chunks",
+        region.x,
+        region.z,
+        region.chunks.len()
+    );
+    print_corrupt(&region);
+    println!("Largest chunks:");
+    for chunk in chunks.iter().take(top) {
+        let block_entities = region::block_entities(&chunk.root)?.map_or(0, |list| list.len());
+        println!(
+            "Chunk {},{}: {} bytes, {} block entities{}",
+            chunk.x,
+            chunk.z,
+            chunk.root.size(),
+            block_entities,
+            if chunk.external { " (external)" } else { "" }
+        );
+    }
+    let mut block_entities = Vec::new();
+    for chunk in &region.chunks {
+        block_entities.extend(region::rank_block_entities(&chunk.root)?);
+    }
+    block_entities.sort_by_key(|entry| Reverse(entry.size));
+    println!("Largest block entities:");
+    for entry in block_entities.iter().take(top) {
+        println!(
+            "{} at {},{},{}: {} bytes",
+            entry.id, entry.x, entry.y, entry.z, entry.size
+        );
     }
 
-    let size = compound["Inventory"].size();
-    println!("Total inventory size is {} bytes", size);
-    println!("All inventory items ranked by size:");
-    for item in &items {
-        println!("Slot {}: {} bytes ({})", item.index, item.size, item.id);
+    let mut removals = Vec::new();
+    for spec in matches
+        .values_of("remove_block_entity")
+        .into_iter()
+        .flatten()
+    {
+        removals.push((parse_coords(spec, 3)?, None));
+    }
+    for spec in matches.values_of("remove_item").into_iter().flatten() {
+        let (pos, slot) = spec
+            .split_once('/')
+            .context("Expected <x>,<y>,<z>/<slot>")?;
+        let slot: i8 = slot.parse().context("Invalid slot")?;
+        removals.push((parse_coords(pos, 3)?, Some(slot)));
+    }
+    if removals.is_empty() {
+        return Ok(());
+    }
+    if !matches.is_present("yes") && !confirm("Delete the selected block entities and items?")? {
+        println!("Aborted");
+        return Ok(());
     }
 
-    print!("Which slot would you like to delete? ");
-    std::io::stdout().flush()?;
-    let n = get_input()?.trim().parse::<usize>()?;
+    let mut removed_size = 0;
+    for (pos, slot) in removals {
+        let removed = match slot {
+            Some(slot) => {
+                region::remove_block_entity_item(&mut region, pos[0], pos[1], pos[2], slot)?
+            }
+            None => region::remove_block_entity(&mut region, pos[0], pos[1], pos[2])?,
+        };
+        removed_size += removed.size();
+    }
+    println!("Compressing...");
+    let folded = region.write(path, &write_options(matches)?)?;
+    for (x, z) in folded {
+        println!("Moved chunk {},{} back into the region file", x, z);
+    }
+    println!("Done! Removed {} bytes", removed_size);
+    Ok(())
+}
 
-    let item = items
-        .iter()
-        .find(|item| item.index == n)
-        .context("Slot not found")?;
-    println!("Deleting item...");
-    // Fix length of list
-    let len_range = inventory_val.start + 1..inventory_val.start + 5;
-    data.splice(len_range, (inventory.len() as i32 - 1).to_be_bytes());
-    // Remove item in list
-    data.drain(item.start..item.end);
+fn fix_entities(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let mut region = Region::read(path)?;
+    let top = match matches.value_of("top") {
+        Some(top) => top.parse().context("Invalid number of entries")?,
+        None => 10,
+    };
 
+    let ranked = entity::rank_entities(&region)?;
+    println!(
+        "Region {},{} has {} entities in {} chunks",
+        region.x,
+        region.z,
+        ranked.len(),
+        region.chunks.len()
+    );
+    print_corrupt(&region);
+    println!("Largest entities:");
+    for entry in ranked.iter().take(top) {
+        let pos = match entry.pos {
+            Some([x, y, z]) => format!("{:.1},{:.1},{:.1}", x, y, z),
+            None => "unknown position".to_string(),
+        };
+        println!(
+            "{} at {} in chunk {},{} ({}): {} bytes",
+            entry.id,
+            pos,
+            entry.chunk_x,
+            entry.chunk_z,
+            entry.uuid.as_deref().unwrap_or("no UUID"),
+            entry.size
+        );
+    }
+
+    let removals: Vec<&str> = matches.values_of("remove").into_iter().flatten().collect();
+    let clears: Vec<&str> = matches
+        .values_of("clear_items")
+        .into_iter()
+        .flatten()
+        .collect();
+    if removals.is_empty() && clears.is_empty() {
+        return Ok(());
+    }
+    if !matches.is_present("yes") && !confirm("Modify the selected entities?")? {
+        println!("Aborted");
+        return Ok(());
+    }
 
+    let mut removed_size = 0;
+    for uuid in removals {
+        removed_size += entity::remove_entity(&mut region, uuid)?.size();
+    }
+    for uuid in clears {
+        removed_size += entity::clear_entity_items(&mut region, uuid)?;
+    }
+    println!("Compressing...");
+    let folded = region.write(path, &write_options(matches)?)?;
+    for (x, z) in folded {
+        println!("Moved chunk {},{} back into the region file", x, z);
+    }
+    println!("Done! Removed {} bytes", removed_size);
+    Ok(())
+}
+
+fn fix_level(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let mut nbt_file = file::read_file(path)?;
+    let nbt = &nbt_file.root;
+    let top = match matches.value_of("top") {
+        Some(top) => top.parse().context("Invalid number of entries")?,
+        None => 20,
+    };
+    let mut removals = Vec::new();
+    for spec in matches.values_of("remove").into_iter().flatten() {
+        removals.push(spec.parse::<NBTPath>()?);
+    }
+
+    let size = nbt.size();
+    println!("{} is {} bytes", path.display(), size);
+    println!("Largest subtrees:");
+    for subtree in path::rank_subtrees(nbt).iter().take(top) {
+        println!(
+            "{}: {} bytes ({:.1}%)",
+            subtree.path,
+            subtree.size,
+            subtree.size as f64 * 100.0 / size as f64
+        );
+    }
+    if "Data.Player".parse::<NBTPath>()?.get(nbt).is_some() {
+        println!("Data.Player holds the singleplayer player, pass this file as the input to fix its inventory");
+    }
+
+    if removals.is_empty() {
+        return Ok(());
+    }
+    remove_paths(path, &mut nbt_file, &removals, matches)
+}
+
+/// Removes every value matched by the paths from a file after listing them, reporting the bytes
+/// saved by each path.
+fn remove_paths(
+    path: &Path,
+    nbt_file: &mut NBTFile,
+    paths: &[NBTPath],
+    matches: &ArgMatches,
+) -> Result<()> {
+    let targets = path::Targets::find(&nbt_file.root, paths)?;
+    for (pattern, removal) in paths.iter().zip(targets.removals(&nbt_file.root)) {
+        println!(
+            "{}: {} matches, {} bytes",
+            pattern, removal.matches, removal.saved
+        );
+    }
+    if targets.is_empty() {
+        println!("Nothing matched, nothing to do");
+        return Ok(());
+    }
+    if !matches.is_present("yes") && !confirm("Delete the matched values?")? {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    let removals = targets.remove(&mut nbt_file.root)?;
+    let mut saved = 0;
+    for (pattern, removal) in paths.iter().zip(&removals) {
+        println!(
+            "Removed {} values at {}, saving {} bytes",
+            removal.matches, pattern, removal.saved
+        );
+        saved += removal.saved;
+    }
     println!("Compressing...");
-    let file = File::create(path)?;
-    let mut encoder = GzEncoder::new(file, Compression::new(9));
-    encoder.write_all(&data)?;
+    file::write_file(path, nbt_file, &write_options(matches)?)?;
+    println!("Done! Saved {} bytes", saved);
+    Ok(())
+}
+
+fn query(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let query: NBTPath = matches
+        .value_of("path")
+        .context("path arg missing")?
+        .parse()?;
+    let nbt = file::read_file(path)?.root;
+    let results = query.expand(&nbt);
+    if results.is_empty() {
+        bail!("Nothing matched {}", query);
+    }
+    for result in results {
+        let value = result.get(&nbt).unwrap();
+        println!("{}: {}", result, snbt::to_snbt(value));
+    }
+    Ok(())
+}
+
+fn export_snbt(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let nbt = file::read_file(path)?.root;
+    let mut options = snbt::FormatOptions {
+        indent: Some(4),
+        max_string: Some(100),
+        max_elements: Some(20),
+    };
+    if matches.is_present("compact") {
+        options.indent = None;
+    }
+    if let Some(max) = matches.value_of("max_string") {
+        options.max_string = Some(max.parse().context("Invalid string length")?);
+    }
+    if let Some(max) = matches.value_of("max_elements") {
+        options.max_elements = Some(max.parse().context("Invalid number of elements")?);
+    }
+    if matches.is_present("full") {
+        options.max_string = None;
+        options.max_elements = None;
+    }
+
+    let query: NBTPath = matches.value_of("path").unwrap_or("").parse()?;
+    let results = query.expand(&nbt);
+    if results.is_empty() {
+        bail!("Nothing matched {}", query);
+    }
+    for result in results {
+        if !result.0.is_empty() {
+            println!("{}:", result);
+        }
+        println!("{}", snbt::format(result.get(&nbt).unwrap(), &options));
+    }
+    Ok(())
+}
+
+fn export_json(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let json = json::file_to_json(&file::read_file(path)?);
+    let text = if matches.is_present("compact") {
+        serde_json::to_string(&json)?
+    } else {
+        serde_json::to_string_pretty(&json)?
+    };
+    match matches.value_of("output") {
+        Some(output) => std::fs::write(output, text)?,
+        None => println!("{}", text),
+    }
+    Ok(())
+}
 
-    println!("Done! New inventory size is {} bytes", size - item.size);
+fn import(matches: &ArgMatches) -> Result<()> {
+    let input = matches.value_of("input").context("input arg missing")?;
+    let text = if input == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        std::fs::read_to_string(input)?
+    };
+    let imported = if matches.is_present("json") {
+        let json =
+            serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", input))?;
+        json::file_from_json(&json).with_context(|| format!("Invalid NBT in {}", input))?
+    } else {
+        NBTFile::new(snbt::parse(&text).with_context(|| format!("Failed to parse {}", input))?)
+    };
+    let value = imported.root;
+    let output = Path::new(matches.value_of("output").context("output arg missing")?);
+    let options = write_options(matches)?;
+
+    if let Some(target) = matches.value_of("set") {
+        let target: NBTPath = target.parse()?;
+        let mut nbt_file = file::read_file(output)?;
+        match target.set(&mut nbt_file.root, value)? {
+            Some(replaced) => println!("Replaced {} bytes at {}", replaced.size(), target),
+            None => println!("Added {}", target),
+        }
+        return file::write_file(output, &nbt_file, &options);
+    }
+    if let Some(target) = matches.value_of("append") {
+        let target: NBTPath = target.parse()?;
+        let mut nbt_file = file::read_file(output)?;
+        let list = target
+            .get_mut(&mut nbt_file.root)
+            .with_context(|| format!("Nothing at {}", target))?;
+        let list = match &mut list.ty {
+            ValueType::List(list) => list,
+            _ => bail!("{} is not a list", target),
+        };
+        if let Some(first) = list.first() {
+            if first.ty.type_id() != value.ty.type_id() {
+                bail!(
+                    "The elements of the list at {} have a different type",
+                    target
+                );
+            }
+        }
+        list.push(value);
+        println!("Appended to {}, it now has {} elements", target, list.len());
+        return file::write_file(output, &nbt_file, &options);
+    }
+
+    if !matches!(value.ty, ValueType::Compound(_)) {
+        bail!("The root of an NBT file must be a compound");
+    }
+    let nbt_file = NBTFile {
+        root: value,
+        ..imported
+    };
+    file::write_file(output, &nbt_file, &options)
+}
+
+fn remove_tags(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let mut paths = Vec::new();
+    for spec in matches.values_of("paths").into_iter().flatten() {
+        paths.push(spec.parse::<NBTPath>()?);
+    }
+    let mut nbt_file = file::read_file(path)?;
+    remove_paths(path, &mut nbt_file, &paths, matches)
+}
+
+/// Names the type of a value, along with how many elements it has if it holds several.
+fn value_kind(ty: &ValueType) -> String {
+    match ty {
+        ValueType::Byte(_) => "byte".to_string(),
+        ValueType::Short(_) => "short".to_string(),
+        ValueType::Int(_) => "int".to_string(),
+        ValueType::Long(_) => "long".to_string(),
+        ValueType::Float(_) => "float".to_string(),
+        ValueType::Double(_) => "double".to_string(),
+        ValueType::String(_) => "string".to_string(),
+        ValueType::ByteArray(array) => format!("byte array of {}", array.len()),
+        ValueType::IntArray(array) => format!("int array of {}", array.len()),
+        ValueType::LongArray(array) => format!("long array of {}", array.len()),
+        ValueType::List(list) => format!("list of {}", list.len()),
+        ValueType::Compound(compound) => format!("compound of {}", compound.len()),
+    }
+}
+
+fn print_tree(
+    value: &NBTValue,
+    name: &str,
+    parent_size: usize,
+    depth: usize,
+    max_depth: Option<usize>,
+    sort: bool,
+) {
+    println!(
+        "{}{}: {} bytes ({:.1}%, {})",
+        "  ".repeat(depth),
+        name,
+        value.size(),
+        value.size() as f64 * 100.0 / parent_size.max(1) as f64,
+        value_kind(&value.ty)
+    );
+    if max_depth == Some(depth) {
+        return;
+    }
+    let mut children = path::children(value);
+    if sort {
+        children.sort_by_key(|(_, child)| Reverse(child.size()));
+    }
+    for (segment, child) in children {
+        let name = NBTPath(vec![segment]).to_string();
+        print_tree(child, &name, value.size(), depth + 1, max_depth, sort);
+    }
+}
+
+fn tree(matches: &ArgMatches) -> Result<()> {
+    let path = Path::new(matches.value_of("file").context("file arg missing")?);
+    let nbt = file::read_file(path)?.root;
+    let max_depth = match matches.value_of("depth") {
+        Some(depth) => Some(depth.parse().context("Invalid depth")?),
+        None => None,
+    };
+    print_tree(
+        &nbt,
+        "<root>",
+        nbt.size(),
+        0,
+        max_depth,
+        matches.is_present("sort"),
+    );
     Ok(())
 }
+
+/// The `--backups` argument of the commands that modify files.
+fn backups_arg() -> Arg<'static, 'static> {
+    Arg::with_name("backups")
+        .long("backups")
+        .takes_value(true)
+        .help("How many backups to keep of each modified file, 0 disables them (default 5)")
+}
+
+/// The `--compression` argument of the commands that write files.
+fn compression_arg() -> Arg<'static, 'static> {
+    Arg::with_name("compression")
+        .long("compression")
+        .takes_value(true)
+        .possible_values(&["gzip", "zlib", "none"])
+        .help("Compression for the written files, defaults to the one they were read with or gzip for new files")
+}
+
+/// The subcommands, which are kept apart from the main arguments so they can be left out when
+/// the first argument is a file.
+fn subcommands() -> Vec<App<'static, 'static>> {
+    vec![
+        clap_app!(restore =>
+            (about: "Restores an item from a quarantine file to a player")
+            (@arg quarantine: +required "The quarantine file")
+            (@arg player: "The player.dat file to restore the item to, lists the quarantined items if left out")
+            (@arg entry: --entry +takes_value "The number of the quarantined item to restore")
+            (@arg into: --into +takes_value possible_values(&["inventory", "ender"])
+                "Which list to restore the item to, defaults to the one it was taken from")
+            (@arg slot: --slot +takes_value +allow_hyphen_values
+                "Which slot to restore the item to, defaults to the one it was taken from")
+            (@arg keep: --keep "Keeps the item in the quarantine file after restoring it")
+        )
+        .arg(backups_arg()),
+        clap_app!(region =>
+            (about: "Finds and removes large block entities and items in a region file")
+            (@arg file: +required "The r.<x>.<z>.mca region file")
+            (@arg top: --top +takes_value "How many chunks and block entities to list (default 10)")
+            (@arg chunk: --chunk +takes_value +allow_hyphen_values
+                "Lists the block entities and items in the chunk at <x>,<z>")
+            (@arg remove_block_entity: --("remove-block-entity") +takes_value +multiple
+                number_of_values(1) +allow_hyphen_values
+                "Removes the block entity at the block <x>,<y>,<z>")
+            (@arg remove_item: --("remove-item") +takes_value +multiple number_of_values(1)
+                +allow_hyphen_values
+                "Removes an item from a container block entity, given as <x>,<y>,<z>/<slot>")
+            (@arg yes: -y --yes "Removes the selected entries without asking for confirmation")
+        )
+        .arg(backups_arg()),
+        clap_app!(level =>
+            (about: "Finds and removes large parts of a level.dat file")
+            (@arg file: +required "The level.dat file")
+            (@arg top: --top +takes_value "How many subtrees to list (default 20)")
+            (@arg remove: --remove +takes_value +multiple number_of_values(1)
+                "Removes the values at this path, such as Data.DataPacks.Enabled[3] or Data.DataPacks.Enabled[*]")
+            (@arg yes: -y --yes "Removes the selected subtrees without asking for confirmation")
+        )
+        .arg(backups_arg())
+        .arg(compression_arg()),
+        clap_app!(remove =>
+            (about: "Removes the values at the given paths from an NBT file")
+            (@arg file: +required "The NBT file")
+            (@arg paths: +required +multiple
+                "Paths of the values to remove, such as Inventory[3].tag.pages or EnderItems[*].tag.BlockEntityTag")
+            (@arg yes: -y --yes "Removes the matched values without asking for confirmation")
+        )
+        .arg(backups_arg())
+        .arg(compression_arg()),
+        clap_app!(query =>
+            (about: "Prints the values at an NBT path, such as Pos or Inventory[{Slot:0b}].id")
+            (@arg file: +required "The NBT file")
+            (@arg path: +required "The path of the values to print")
+        ),
+        clap_app!(snbt =>
+            (about: "Prints an NBT file or part of it as SNBT")
+            (@arg file: +required "The NBT file")
+            (@arg path: "The path of the values to print, such as Inventory[{Slot:1b}], defaults to the whole file")
+            (@arg compact: --compact "Prints each value on a single line")
+            (@arg max_string: --("max-string") +takes_value
+                "Cuts off strings longer than this many characters (default 100)")
+            (@arg max_elements: --("max-elements") +takes_value
+                "Leaves out the list and array elements after this many (default 20)")
+            (@arg full: --full "Prints every string and element in full")
+        ),
+        clap_app!(json =>
+            (about: "Converts an NBT file to typed JSON that can be imported back without changes")
+            (@arg file: +required "The NBT file")
+            (@arg output: -o --output +takes_value "Writes the JSON to this file instead of printing it")
+            (@arg compact: --compact "Leaves out the indentation and line breaks")
+        ),
+        clap_app!(import =>
+            (about: "Writes SNBT or typed JSON as a binary NBT file, or into part of one")
+            (@arg input: +required "The SNBT or JSON file to read, - for stdin")
+            (@arg json: --json "Reads the typed JSON written by the json subcommand instead of SNBT")
+            (@arg output: +required "The NBT file to write")
+            (@arg set: --set +takes_value conflicts_with[append]
+                "Puts the value at this path of the existing output file instead of replacing it")
+            (@arg append: --append +takes_value
+                "Adds the value to the list at this path of the existing output file, such as Inventory")
+        )
+        .arg(backups_arg())
+        .arg(compression_arg()),
+        clap_app!(tree =>
+            (about: "Prints the size of every tag in an NBT file")
+            (@arg file: +required "The NBT file")
+            (@arg depth: --depth +takes_value "Hides the tags nested deeper than this")
+            (@arg sort: --sort "Lists the largest tags first instead of in file order")
+        ),
+        clap_app!(entities =>
+            (about: "Finds and removes large entities in a region file of the entities folder")
+            (@arg file: +required "The entities/r.<x>.<z>.mca region file")
+            (@arg top: --top +takes_value "How many entities to list (default 10)")
+            (@arg remove: --remove +takes_value +multiple number_of_values(1)
+                "Removes the entity with this UUID")
+            (@arg clear_items: --("clear-items") +takes_value +multiple number_of_values(1)
+                "Removes the items held, worn or carried by the entity with this UUID")
+            (@arg yes: -y --yes "Modifies the selected entities without asking for confirmation")
+        )
+        .arg(backups_arg()),
+    ]
+}
+
+fn main() -> Result<()> {
+    // clap takes a first argument that looks like the name of a subcommand, such as level.dat,
+    // for a misspelled one, so the subcommands are only added if it isn't a file
+    let subcommands = subcommands();
+    let first = std::env::args().nth(1).unwrap_or_default();
+    let is_input = first == "--"
+        || !first.is_empty()
+            && !first.starts_with('-')
+            && first != "help"
+            && !subcommands.iter().any(|app| app.get_name() == first);
+
+    let mut app = clap_app!(large_nbt_fixer =>
+        (version: "1.0")
+        (author: "StackDoubleFlow <ojaslandge@gmail.com>")
+        (about: "Removes large nbt from player.dat files")
+        (@arg input: +required
+            "The player.dat or level.dat file to modify, or a world or playerdata folder to scan every player")
+        (@arg index: --index +takes_value +multiple number_of_values(1)
+            "Removes the item at this position in the inventory list, prefix with ender: for the ender chest")
+        (@arg slot: --slot +takes_value +multiple number_of_values(1) +allow_hyphen_values
+            "Removes the item in this inventory slot (0-8 hotbar, 9-35 main, 100-103 armor, -106 offhand), prefix with ender: for the ender chest")
+        (@arg id: --id +takes_value +multiple number_of_values(1)
+            "Removes every item with this id")
+        (@arg largest: --largest +takes_value "Removes the n largest items")
+        (@arg max_size: --("max-size") +takes_value
+            "Removes every item larger than this many bytes")
+        (@arg nested: --nested "Lists the items inside shulker boxes, bundles and other containers")
+        (@arg remove_nested: --("remove-nested") +takes_value +multiple number_of_values(1)
+            +allow_hyphen_values
+            "Removes an item inside a container, given as <slot>/<index> as shown by --nested")
+        (@arg yes: -y --yes "Removes the selected items without asking for confirmation")
+        (@arg top: --top +takes_value "How many players to list when scanning a folder")
+        (@arg quarantine: --quarantine +takes_value
+            "Saves the removed items to this quarantine file so they can be restored later")
+        (@arg format: --format +takes_value possible_values(&["text", "json", "csv"])
+            "Prints the item ranking as JSON or CSV instead of text, without removing anything")
+        (@setting SubcommandsNegateReqs)
+    )
+    .arg(backups_arg())
+    .arg(compression_arg());
+    if !is_input {
+        // Arguments before a file would otherwise have the same problem
+        app = app
+            .subcommands(subcommands)
+            .setting(AppSettings::ArgsNegateSubcommands);
+    }
+    let matches = app.get_matches();
+
+    match matches.subcommand() {
+        ("restore", Some(matches)) => return restore(matches),
+        ("region", Some(matches)) => return fix_region(matches),
+        ("entities", Some(matches)) => return fix_entities(matches),
+        ("level", Some(matches)) => return fix_level(matches),
+        ("tree", Some(matches)) => return tree(matches),
+        ("remove", Some(matches)) => return remove_tags(matches),
+        ("query", Some(matches)) => return query(matches),
+        ("snbt", Some(matches)) => return export_snbt(matches),
+        ("import", Some(matches)) => return import(matches),
+        ("json", Some(matches)) => return export_json(matches),
+        _ => {}
+    }
+
+    let path = Path::new(matches.value_of("input").context("input arg missing")?);
+    let selectors = parse_selectors(&matches)?;
+    match matches.value_of("format") {
+        Some(format) if format != "text" => {
+            if !selectors.is_empty() || matches.is_present("remove_nested") {
+                bail!("--format only prints the ranking, it can't be combined with removals");
+            }
+            let files = if path.is_dir() {
+                file::player_files(path)?
+            } else {
+                vec![path.to_path_buf()]
+            };
+            return print_report(&files, format);
+        }
+        _ => {}
+    }
+    if path.is_dir() {
+        if matches.is_present("remove_nested") {
+            bail!("--remove-nested only works on a single player file");
+        }
+        fix_directory(path, &matches, &selectors)
+    } else {
+        fix_file(path, &matches, &selectors)
+    }
+}
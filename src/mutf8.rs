@@ -0,0 +1,139 @@
+//! Java's Modified UTF-8, which NBT stores its strings in. It differs from UTF-8 in encoding NUL
+//! as the two bytes `C0 80` and characters outside the Basic Multilingual Plane as a surrogate
+//! pair of three bytes each.
+
+use anyhow::{bail, Result};
+use std::borrow::Cow;
+
+/// Reads the low six bits of a continuation byte.
+fn continuation(iter: &mut impl Iterator<Item = u8>) -> Result<u16> {
+    match iter.next() {
+        Some(b) if b & 0xc0 == 0x80 => Ok((b & 0x3f) as u16),
+        _ => bail!("Malformed modified UTF-8 string"),
+    }
+}
+
+/// Decodes Modified UTF-8. Standard UTF-8 is accepted too, since some tools write it.
+///
+/// Not every input comes back byte for byte from `encode`: a raw NUL byte is written back as
+/// `C0 80`, four byte sequences as surrogate pairs, and unpaired surrogates, which Java strings
+/// can hold but Rust ones can't, become U+FFFD.
+pub fn decode(bytes: &[u8]) -> Result<String> {
+    if bytes.is_ascii() {
+        return Ok(String::from_utf8(bytes.to_vec())?);
+    }
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().copied();
+    while let Some(b) = iter.next() {
+        let unit = match b {
+            0x00..=0x7f => b as u16,
+            0xc0..=0xdf => ((b & 0x1f) as u16) << 6 | continuation(&mut iter)?,
+            0xe0..=0xef => {
+                let high = ((b & 0x0f) as u16) << 12 | continuation(&mut iter)? << 6;
+                high | continuation(&mut iter)?
+            }
+            0xf0..=0xf4 => {
+                let mut code = ((b & 0x07) as u32) << 18;
+                for shift in &[12, 6, 0] {
+                    code |= (continuation(&mut iter)? as u32) << shift;
+                }
+                match std::char::from_u32(code) {
+                    Some(c) if code >= 0x10000 => {
+                        units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+                        continue;
+                    }
+                    _ => bail!("Malformed modified UTF-8 string"),
+                }
+            }
+            _ => bail!("Malformed modified UTF-8 string"),
+        };
+        units.push(unit);
+    }
+    Ok(char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
+/// Encodes a string as Modified UTF-8, borrowing it if it doesn't differ from its UTF-8.
+pub fn encode(string: &str) -> Cow<'_, [u8]> {
+    // Lead bytes of 0xf0 and up start the four byte sequences of supplementary characters
+    if string.bytes().all(|b| b != 0 && b < 0xf0) {
+        return Cow::Borrowed(string.as_bytes());
+    }
+
+    let mut bytes = Vec::with_capacity(string.len() + 2);
+    for c in string.chars() {
+        match c {
+            '\0' => bytes.extend_from_slice(&[0xc0, 0x80]),
+            c if (c as u32) < 0x10000 => {
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+            }
+            c => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    let unit = *unit;
+                    bytes.push(0xe0 | (unit >> 12) as u8);
+                    bytes.push(0x80 | (unit >> 6 & 0x3f) as u8);
+                    bytes.push(0x80 | (unit & 0x3f) as u8);
+                }
+            }
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(string: &str, encoded: &[u8]) {
+        assert_eq!(&*encode(string), encoded);
+        assert_eq!(decode(encoded).unwrap(), string);
+    }
+
+    #[test]
+    fn ascii() {
+        round_trip("minecraft:stone", b"minecraft:stone");
+        assert!(matches!(encode("stone"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn nul() {
+        round_trip("a\0b", &[b'a', 0xc0, 0x80, b'b']);
+    }
+
+    #[test]
+    fn basic_multilingual_plane() {
+        round_trip("\u{e9}", &[0xc3, 0xa9]);
+        round_trip("\u{20ac}", &[0xe2, 0x82, 0xac]);
+        round_trip("\u{ffff}", &[0xef, 0xbf, 0xbf]);
+    }
+
+    #[test]
+    fn supplementary() {
+        // U+1F600 is the surrogate pair D83D DE00
+        round_trip("\u{1f600}", &[0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80]);
+        round_trip("\u{10ffff}", &[0xed, 0xaf, 0xbf, 0xed, 0xbf, 0xbf]);
+    }
+
+    #[test]
+    fn standard_utf8_fallback() {
+        assert_eq!(decode(&[0xf0, 0x9f, 0x98, 0x80]).unwrap(), "\u{1f600}");
+        assert_eq!(decode(&[b'a', 0x00]).unwrap(), "a\0");
+        // Overlong and out of range four byte sequences
+        assert!(decode(&[0xf0, 0x8f, 0xbf, 0xbf]).is_err());
+        assert!(decode(&[0xf4, 0x90, 0x80, 0x80]).is_err());
+    }
+
+    #[test]
+    fn unpaired_surrogate() {
+        assert_eq!(decode(&[0xed, 0xa0, 0xbd, b'a']).unwrap(), "\u{fffd}a");
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(decode(&[0xe2, 0x82]).is_err());
+        assert!(decode(&[0x80]).is_err());
+        assert!(decode(&[0xf8, 0x80, 0x80, 0x80]).is_err());
+    }
+}
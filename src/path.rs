@@ -0,0 +1,485 @@
+//! Paths to values inside an NBT tree, following Minecraft's NBT path syntax. Compound keys are
+//! separated by dots and list positions go in brackets, like `Data.DataPacks.Enabled[3]`.
+//! Keys with other characters than letters, digits, `_`, `-`, `+` and `:` are quoted, negative
+//! positions count from the end of the list and `[]` or `[*]` match every element. SNBT
+//! compounds filter what is matched: `Inventory[{Slot:0b}]` matches the list elements with that
+//! slot, and `Inventory[0]{id:"minecraft:stone"}` only matches the first element if it is stone.
+
+use crate::snbt;
+use crate::{mutf8, NBTValue, ValueType};
+use anyhow::{bail, Context, Result};
+use std::cmp::Reverse;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Segment {
+    /// An entry of a compound
+    Key(String),
+    /// An element of a list
+    Index(usize),
+    /// An element of a list, counting back from the end
+    IndexFromEnd(usize),
+    /// Every element of a list
+    AnyIndex,
+    /// Every element of a list matching the SNBT compound
+    ListFilter(String),
+    /// The current value, if it matches the SNBT compound
+    Filter(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NBTPath(pub Vec<Segment>);
+
+/// What removing every value matched by a path took out of the tree.
+#[derive(Debug, Clone, Default)]
+pub struct Removal {
+    pub matches: usize,
+    /// How many bytes the serialized tree shrunk by
+    pub saved: usize,
+}
+
+/// A value of an NBT tree along with where it is.
+#[derive(Debug, Clone)]
+pub struct Subtree {
+    pub path: NBTPath,
+    pub size: usize,
+}
+
+fn is_bare_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-+:".contains(c)
+}
+
+impl fmt::Display for NBTPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) => {
+                    if n > 0 {
+                        f.write_str(".")?;
+                    }
+                    if !key.is_empty() && key.chars().all(is_bare_char) {
+                        f.write_str(key)?;
+                    } else {
+                        let escaped = key.replace('\\', "\\\\").replace('"', "\\\"");
+                        write!(f, "\"{}\"", escaped)?;
+                    }
+                }
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::IndexFromEnd(index) => write!(f, "[-{}]", index)?,
+                Segment::AnyIndex => f.write_str("[*]")?,
+                Segment::ListFilter(filter) => write!(f, "[{}]", filter)?,
+                Segment::Filter(filter) => f.write_str(filter)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for NBTPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '[' if rest[1..].trim_start().starts_with('{') => {
+                    let (filter, len) = read_filter(&rest[1..])?;
+                    rest = rest[1 + len..]
+                        .trim_start()
+                        .strip_prefix(']')
+                        .with_context(|| format!("Expected ] after {} in path {}", filter, s))?;
+                    segments.push(Segment::ListFilter(filter));
+                }
+                '[' => {
+                    let end = rest.find(']').context("Unterminated [ in path")?;
+                    let index = &rest[1..end];
+                    let invalid = || format!("Invalid list index {:?}", index);
+                    segments.push(match index {
+                        "" | "*" => Segment::AnyIndex,
+                        _ => match index.strip_prefix('-') {
+                            Some(index) => match index.parse().with_context(invalid)? {
+                                0 => bail!(invalid()),
+                                index => Segment::IndexFromEnd(index),
+                            },
+                            None => Segment::Index(index.parse().with_context(invalid)?),
+                        },
+                    });
+                    rest = &rest[end + 1..];
+                }
+                '{' => {
+                    let (filter, len) = read_filter(rest)?;
+                    segments.push(Segment::Filter(filter));
+                    rest = &rest[len..];
+                }
+                _ => {
+                    if !segments.is_empty() {
+                        rest = rest.strip_prefix('.').with_context(|| {
+                            format!(
+                                "Expected . or [ after {} in path {}",
+                                NBTPath(segments.clone()),
+                                s
+                            )
+                        })?;
+                    }
+                    let (key, len) = read_key(rest, s)?;
+                    segments.push(Segment::Key(key));
+                    rest = &rest[len..];
+                }
+            }
+        }
+        Ok(NBTPath(segments))
+    }
+}
+
+/// Reads the SNBT compound at the start of `s`, returning its text and length.
+fn read_filter(s: &str) -> Result<(String, usize)> {
+    let (filter, len) = snbt::parse_prefix(s).context("Invalid filter in path")?;
+    if !matches!(filter.ty, ValueType::Compound(_)) {
+        bail!("Filters in paths must be compounds");
+    }
+    Ok((s[..len].trim().to_string(), len))
+}
+
+/// Reads the quoted or bare key at the start of `s`, returning it and its length.
+fn read_key(s: &str, path: &str) -> Result<(String, usize)> {
+    if let Some(quoted) = s.strip_prefix('"') {
+        let mut key = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((key, i + 2)),
+                '\\' => key.extend(chars.next().map(|(_, c)| c)),
+                c => key.push(c),
+            }
+        }
+        bail!("Unterminated quoted key in path {}", path);
+    }
+    let len = s.find(&['.', '[', '{'][..]).unwrap_or(s.len());
+    if len == 0 {
+        bail!("Empty key in path {}", path);
+    }
+    Ok((s[..len].to_string(), len))
+}
+
+impl NBTPath {
+    /// Returns the concrete paths of every value this path matches, resolving wildcards,
+    /// filters and positions from the end.
+    pub fn expand(&self, root: &NBTValue) -> Vec<NBTPath> {
+        let mut paths = vec![(NBTPath::default(), root)];
+        for segment in &self.0 {
+            let filter = match segment {
+                Segment::ListFilter(filter) | Segment::Filter(filter) => {
+                    snbt::parse_prefix(filter).ok().map(|(filter, _)| filter)
+                }
+                _ => None,
+            };
+            let filter_matches =
+                |value: &NBTValue| filter.as_ref().is_some_and(|f| snbt::matches(f, value));
+            if let Segment::Filter(_) = segment {
+                paths.retain(|(_, value)| filter_matches(value));
+                continue;
+            }
+
+            let mut next = Vec::new();
+            for (path, value) in paths {
+                let len = match &value.ty {
+                    ValueType::List(list) => list.len(),
+                    _ => 0,
+                };
+                for (child_segment, child) in children(value) {
+                    let matched = match (segment, &child_segment) {
+                        (Segment::AnyIndex, Segment::Index(_)) => true,
+                        (Segment::IndexFromEnd(n), Segment::Index(index)) => {
+                            len.checked_sub(*n) == Some(*index)
+                        }
+                        (Segment::ListFilter(_), Segment::Index(_)) => filter_matches(child),
+                        (segment, child_segment) => segment == child_segment,
+                    };
+                    if matched {
+                        let mut path = path.clone();
+                        path.0.push(child_segment);
+                        next.push((path, child));
+                    }
+                }
+            }
+            paths = next;
+        }
+        paths.into_iter().map(|(path, _)| path).collect()
+    }
+
+    fn is_ancestor_of(&self, other: &NBTPath) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+
+    /// Returns the value at this path, if there is one.
+    pub fn get<'a>(&self, root: &'a NBTValue) -> Option<&'a NBTValue> {
+        self.0
+            .iter()
+            .try_fold(root, |value, segment| match (&value.ty, segment) {
+                (ValueType::Compound(compound), Segment::Key(key)) => compound.get(key),
+                (ValueType::List(list), Segment::Index(index)) => list.get(*index),
+                _ => None,
+            })
+    }
+
+    pub fn get_mut<'a>(&self, root: &'a mut NBTValue) -> Option<&'a mut NBTValue> {
+        self.0
+            .iter()
+            .try_fold(root, |value, segment| match (&mut value.ty, segment) {
+                (ValueType::Compound(compound), Segment::Key(key)) => compound.get_mut(key),
+                (ValueType::List(list), Segment::Index(index)) => list.get_mut(*index),
+                _ => None,
+            })
+    }
+
+    /// Puts `value` at this path, adding it to its compound or replacing the list element there.
+    /// Returns the value it replaced, if any.
+    pub fn set(&self, root: &mut NBTValue, value: NBTValue) -> Result<Option<NBTValue>> {
+        let (last, parents) = match self.0.split_last() {
+            Some(split) => split,
+            None => return Ok(Some(std::mem::replace(root, value))),
+        };
+        let parent = NBTPath(parents.to_vec())
+            .get_mut(root)
+            .with_context(|| format!("Nothing at {}", NBTPath(parents.to_vec())))?;
+        match (&mut parent.ty, last) {
+            (ValueType::Compound(compound), Segment::Key(key)) => {
+                Ok(compound.insert(key.clone(), value))
+            }
+            (ValueType::List(list), Segment::Index(index)) => {
+                let element = list
+                    .get_mut(*index)
+                    .with_context(|| format!("Nothing at {}", self))?;
+                if element.ty.type_id() != value.ty.type_id() {
+                    bail!("The elements of the list at {} have a different type", self);
+                }
+                Ok(Some(std::mem::replace(element, value)))
+            }
+            _ => bail!("Can't put a value at {}", self),
+        }
+    }
+
+    /// Removes the value at this path from its compound or list.
+    pub fn remove(&self, root: &mut NBTValue) -> Result<NBTValue> {
+        let (last, parents) = match self.0.split_last() {
+            Some(split) => split,
+            None => bail!("Can't remove the root"),
+        };
+        let parent = NBTPath(parents.to_vec())
+            .get_mut(root)
+            .with_context(|| format!("Nothing at {}", self))?;
+        let removed = match (&mut parent.ty, last) {
+            (ValueType::Compound(compound), Segment::Key(key)) => compound.shift_remove(key),
+            (ValueType::List(list), Segment::Index(index)) if *index < list.len() => {
+                Some(list.remove(*index))
+            }
+            _ => None,
+        };
+        removed.with_context(|| format!("Nothing at {}", self))
+    }
+}
+
+/// The values matched by a set of paths. A value matched by several paths counts for the first
+/// of them, and values inside another matched value are left out since they go along with it.
+pub struct Targets {
+    targets: Vec<(NBTPath, usize)>,
+    paths: usize,
+}
+
+impl Targets {
+    pub fn find(root: &NBTValue, paths: &[NBTPath]) -> Result<Targets> {
+        let mut targets: Vec<(NBTPath, usize)> = Vec::new();
+        for (n, path) in paths.iter().enumerate() {
+            if path.0.is_empty() {
+                bail!("Can't remove the root");
+            }
+            for target in path.expand(root) {
+                if !targets.iter().any(|(existing, _)| *existing == target) {
+                    targets.push((target, n));
+                }
+            }
+        }
+        let ancestors: Vec<NBTPath> = targets.iter().map(|(path, _)| path.clone()).collect();
+        targets.retain(|(path, _)| {
+            !ancestors
+                .iter()
+                .any(|ancestor| ancestor.is_ancestor_of(path))
+        });
+        targets.sort();
+        Ok(Targets {
+            targets,
+            paths: paths.len(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// What removing the targets would take out of the tree for each path, without removing
+    /// them.
+    pub fn removals(&self, root: &NBTValue) -> Vec<Removal> {
+        let mut removals = vec![Removal::default(); self.paths];
+        for (target, n) in &self.targets {
+            if let Some(value) = target.get(root) {
+                removals[*n].matches += 1;
+                removals[*n].saved += removed_size(target, value);
+            }
+        }
+        removals
+    }
+
+    /// Removes the targets, returning what was removed for each path.
+    pub fn remove(self, root: &mut NBTValue) -> Result<Vec<Removal>> {
+        let mut removals = vec![Removal::default(); self.paths];
+        // The targets are sorted, so going backwards removes later list elements before earlier
+        // ones, whose positions would otherwise shift
+        for (target, n) in self.targets.into_iter().rev() {
+            let removed = target.remove(root)?;
+            removals[n].matches += 1;
+            removals[n].saved += removed_size(&target, &removed);
+        }
+        Ok(removals)
+    }
+}
+
+/// How many bytes removing the value at `path` takes out of the serialized tree.
+fn removed_size(path: &NBTPath, value: &NBTValue) -> usize {
+    // Compound entries also take up their type id and name
+    let header = match path.0.last() {
+        Some(Segment::Key(key)) => 3 + mutf8::encode(key).len(),
+        _ => 0,
+    };
+    header + value.size()
+}
+
+/// Removes every value matched by any of the paths, returning what was removed for each path,
+/// see `Targets`.
+pub fn remove_matches(root: &mut NBTValue, paths: &[NBTPath]) -> Result<Vec<Removal>> {
+    Targets::find(root, paths)?.remove(root)
+}
+
+/// Returns the entries of a compound or the elements of a list along with the segment leading
+/// to each of them.
+pub fn children(value: &NBTValue) -> Vec<(Segment, &NBTValue)> {
+    match &value.ty {
+        ValueType::Compound(compound) => compound
+            .iter()
+            .map(|(key, child)| (Segment::Key(key.clone()), child))
+            .collect(),
+        ValueType::List(list) => list
+            .iter()
+            .enumerate()
+            .map(|(index, child)| (Segment::Index(index), child))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn collect(value: &NBTValue, path: &mut NBTPath, subtrees: &mut Vec<Subtree>) {
+    subtrees.push(Subtree {
+        path: path.clone(),
+        size: value.size(),
+    });
+    for (segment, child) in children(value) {
+        path.0.push(segment);
+        collect(child, path, subtrees);
+        path.0.pop();
+    }
+}
+
+/// Collects every compound entry and list element of a tree, ranked from largest to smallest.
+pub fn rank_subtrees(root: &NBTValue) -> Vec<Subtree> {
+    let mut subtrees = Vec::new();
+    collect(root, &mut NBTPath::default(), &mut subtrees);
+    subtrees.remove(0);
+    subtrees.sort_by_key(|subtree| Reverse(subtree.size));
+    subtrees
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(snbt: &str) -> NBTValue {
+        // Going through bytes gives the values their sizes
+        let value = snbt::parse(snbt).unwrap();
+        crate::from_bytes(crate::to_bytes(&value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for path in &[
+            "Data.DataPacks.Enabled[3]",
+            "\"a key\".\"with \\\"quotes\\\"\"[-1][*]",
+            "Inventory[{Slot:0b}].tag",
+            "Inventory[0]{id:\"minecraft:stone\"}",
+        ] {
+            assert_eq!(path.parse::<NBTPath>().unwrap().to_string(), *path);
+        }
+        let path: NBTPath = "Items[].id".parse().unwrap();
+        assert_eq!(
+            path.0,
+            [
+                Segment::Key("Items".to_string()),
+                Segment::AnyIndex,
+                Segment::Key("id".to_string())
+            ]
+        );
+        assert_eq!(path.to_string(), "Items[*].id");
+        for invalid in &["Items[", "Items[-0]", "Items[x]", "a..b", "a[0]b"] {
+            assert!(invalid.parse::<NBTPath>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expand() {
+        let root = tree("{Items:[{id:a,Slot:0b},{id:b,Slot:1b},{id:a,Slot:2b}]}");
+        let expanded = |path: &str| -> Vec<String> {
+            let path: NBTPath = path.parse().unwrap();
+            path.expand(&root).iter().map(|p| p.to_string()).collect()
+        };
+        assert_eq!(expanded("Items[-1].id"), ["Items[2].id"]);
+        assert_eq!(expanded("Items[{id:a}]"), ["Items[0]", "Items[2]"]);
+        assert_eq!(expanded("Items[1]{id:a}"), Vec::<String>::new());
+        assert_eq!(expanded("Items[*].Slot").len(), 3);
+    }
+
+    #[test]
+    fn targets_count_each_value_once() {
+        let mut root = tree("{Items:[{id:a},{id:b}],Other:1}");
+        let paths: Vec<NBTPath> = ["Items[0]", "Items[0]", "Items[*]", "Items"]
+            .iter()
+            .map(|path| path.parse().unwrap())
+            .collect();
+        let targets = Targets::find(&root, &paths).unwrap();
+        let preview = targets.removals(&root);
+        // Everything is inside Items, so only the last path removes anything
+        let matches: Vec<usize> = preview.iter().map(|removal| removal.matches).collect();
+        assert_eq!(matches, [0, 0, 0, 1]);
+
+        let size = crate::to_bytes(&root).unwrap().len();
+        let removed = targets.remove(&mut root).unwrap();
+        assert_eq!(removed[3].saved, preview[3].saved);
+        assert_eq!(
+            crate::to_bytes(&root).unwrap().len(),
+            size - removed[3].saved
+        );
+        assert_eq!(snbt::to_snbt(&root), "{Other:1}");
+    }
+
+    #[test]
+    fn targets_remove_list_elements_from_the_end() {
+        let mut root = tree("{Items:[{id:a},{id:b},{id:a}]}");
+        let paths: Vec<NBTPath> = ["Items[{id:a}]", "Items[0]"]
+            .iter()
+            .map(|path| path.parse().unwrap())
+            .collect();
+        let removed = remove_matches(&mut root, &paths).unwrap();
+        assert_eq!(removed[0].matches, 2);
+        assert_eq!(removed[1].matches, 0);
+        assert_eq!(snbt::to_snbt(&root), "{Items:[{id:\"b\"}]}");
+    }
+}
@@ -0,0 +1,147 @@
+//! Removing items from a player file in one go, quarantining them first if asked to. This is
+//! what the main command does once the items to remove are picked.
+
+use crate::container;
+use crate::file::{self, NBTFile, WriteOptions};
+use crate::inventory::{self, ItemEntry, ItemList, Selector};
+use crate::quarantine::{self, QuarantineEntry};
+use crate::NBTValue;
+use anyhow::Result;
+use std::path::Path;
+
+/// An item inside the container in one of a player's slots.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NestedLocation {
+    pub list: ItemList,
+    pub slot: i8,
+    /// Path of the item inside the container, see `container::NestedEntry`
+    pub path: Vec<usize>,
+}
+
+/// A nested item that was taken out of its container.
+#[derive(Debug, Clone)]
+pub struct RemovedNested {
+    pub location: NestedLocation,
+    pub size: usize,
+    pub id: String,
+}
+
+/// Everything removed from a player.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// The removed entries of the item lists, largest first
+    pub items: Vec<ItemEntry>,
+    pub nested: Vec<RemovedNested>,
+}
+
+impl Pruned {
+    pub fn len(&self) -> usize {
+        self.items.len() + self.nested.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The combined size of the removed items.
+    pub fn size(&self) -> usize {
+        let items: usize = self.items.iter().map(|item| item.size).sum();
+        items + self.nested.iter().map(|item| item.size).sum::<usize>()
+    }
+}
+
+/// Identifies a player by their UUID, falling back to the name of their file.
+pub fn player_name(player: &NBTValue, path: &Path) -> String {
+    quarantine::uuid(player).unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default();
+        stem.to_string_lossy().into_owned()
+    })
+}
+
+/// Removes the nested items and then the entries matched by the selectors from a player,
+/// returning what was removed along with quarantine entries holding the removed values. Nested
+/// items in a container the selectors remove are left to go along with it, so they aren't
+/// counted twice.
+pub fn prune(
+    player: &mut NBTValue,
+    name: &str,
+    selectors: &[Selector],
+    nested: &[NestedLocation],
+) -> Result<(Pruned, Vec<QuarantineEntry>)> {
+    let mut pruned = Pruned::default();
+    let mut quarantined_nested = Vec::with_capacity(nested.len());
+
+    // Selected by position so that taking nested items out first doesn't change the selection
+    let (selected, removed_slots): (Vec<Selector>, Vec<(ItemList, Option<i8>)>) = {
+        let ranked = inventory::rank_player(player)?;
+        inventory::select(&ranked, selectors)
+            .into_iter()
+            .map(|item| {
+                (
+                    Selector::Index(item.list, item.index),
+                    (item.list, item.slot),
+                )
+            })
+            .unzip()
+    };
+
+    // Taking later items out of a container first leaves the positions of the earlier ones as
+    // they were given
+    let mut nested: Vec<NestedLocation> = nested
+        .iter()
+        .filter(|location| !removed_slots.contains(&(location.list, Some(location.slot))))
+        .cloned()
+        .collect();
+    nested.sort();
+    for location in nested.into_iter().rev() {
+        let value =
+            container::remove_player_nested(player, location.list, location.slot, &location.path)?;
+        pruned.nested.push(RemovedNested {
+            size: value.size(),
+            id: container::item_id(&value).unwrap_or("unknown").to_string(),
+            location: location.clone(),
+        });
+        quarantined_nested.push(QuarantineEntry::new(
+            name,
+            location.list,
+            Some(location.slot),
+            Some(location.path),
+            value,
+        ));
+    }
+
+    let mut quarantined = Vec::new();
+    for item in inventory::remove_selected(player, &selected)? {
+        let entry = item.entry;
+        quarantined.push(QuarantineEntry::new(
+            name, entry.list, entry.slot, None, item.value,
+        ));
+        pruned.items.push(entry);
+    }
+    quarantined.extend(quarantined_nested);
+    Ok((pruned, quarantined))
+}
+
+/// Prunes the player in a file that was read from `path` and writes it back there, appending
+/// the removed items to the `quarantine` file first if given. Nothing is written if nothing was
+/// removed.
+pub fn prune_file(
+    path: &Path,
+    nbt_file: &mut NBTFile,
+    selectors: &[Selector],
+    nested: &[NestedLocation],
+    options: &WriteOptions,
+    quarantine: Option<&Path>,
+) -> Result<Pruned> {
+    let player = inventory::player_data_mut(&mut nbt_file.root);
+    let name = player_name(player, path);
+    let (pruned, quarantined) = prune(player, &name, selectors, nested)?;
+    if pruned.is_empty() {
+        return Ok(pruned);
+    }
+    if let Some(quarantine) = quarantine {
+        quarantine::append(quarantine, quarantined, options)?;
+    }
+    file::write_file(path, nbt_file, options)?;
+    Ok(pruned)
+}
@@ -0,0 +1,206 @@
+//! Quarantine files keep items removed from players so they can be restored later. They are
+//! gzip compressed NBT files whose root compound holds an `Items` list of entries.
+
+use crate::container;
+use crate::file::{self, NBTFile, WriteOptions};
+use crate::inventory::{self, ItemList};
+use crate::{get_variant, get_variant_mut, Compound, List, NBTValue, ValueType};
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A quarantined item along with where it was taken from.
+pub struct QuarantineEntry {
+    /// UUID of the player the item was taken from
+    pub player: String,
+    pub list: ItemList,
+    /// The item's slot, or the slot of the container it was in
+    pub slot: Option<i8>,
+    /// Path of the item inside its container, see `container::NestedEntry`
+    pub nested: Option<Vec<usize>>,
+    /// When the item was removed, in seconds since the unix epoch
+    pub removed_at: i64,
+    pub item: NBTValue,
+}
+
+impl QuarantineEntry {
+    pub fn new(
+        player: &str,
+        list: ItemList,
+        slot: Option<i8>,
+        nested: Option<Vec<usize>>,
+        item: NBTValue,
+    ) -> Self {
+        let removed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+        Self {
+            player: player.to_string(),
+            list,
+            slot,
+            nested,
+            removed_at,
+            item: container::into_item(item),
+        }
+    }
+
+    fn into_nbt(self) -> NBTValue {
+        let mut compound = Compound::new();
+        let mut insert = |name: &str, ty| {
+            compound.insert(name.to_string(), NBTValue::new(ty));
+        };
+        insert("Player", ValueType::String(self.player));
+        insert("List", ValueType::String(self.list.tag_name().to_string()));
+        if let Some(slot) = self.slot {
+            insert("Slot", ValueType::Byte(slot));
+        }
+        if let Some(nested) = self.nested {
+            let path = nested.into_iter().map(|index| index as i32).collect();
+            insert("Nested", ValueType::IntArray(path));
+        }
+        insert("RemovedAt", ValueType::Long(self.removed_at));
+        compound.insert("Item".to_string(), self.item);
+        NBTValue::new(ValueType::Compound(compound))
+    }
+
+    fn from_nbt(value: NBTValue) -> Result<Self> {
+        let mut compound = match value.ty {
+            ValueType::Compound(compound) => compound,
+            _ => bail!("incorrect variant"),
+        };
+        let list = get_variant!(
+            compound
+                .get("List")
+                .context("Quarantined item has no list")?
+                .ty,
+            ValueType::String
+        );
+        let list = ItemList::ALL
+            .iter()
+            .copied()
+            .find(|l| l.tag_name() == list)
+            .context("Unknown item list")?;
+        let slot = match compound.get("Slot") {
+            Some(slot) => Some(*get_variant!(slot.ty, ValueType::Byte)),
+            None => None,
+        };
+        let nested = match compound.get("Nested") {
+            Some(nested) => {
+                let path = get_variant!(nested.ty, ValueType::IntArray);
+                Some(path.iter().map(|&index| index as usize).collect())
+            }
+            None => None,
+        };
+        Ok(Self {
+            player: get_variant!(
+                compound
+                    .get("Player")
+                    .context("Quarantined item has no player")?
+                    .ty,
+                ValueType::String
+            )
+            .clone(),
+            list,
+            slot,
+            nested,
+            removed_at: *get_variant!(
+                compound
+                    .get("RemovedAt")
+                    .context("Quarantined item has no removal time")?
+                    .ty,
+                ValueType::Long
+            ),
+            item: compound
+                .shift_remove("Item")
+                .context("Quarantined item missing")?,
+        })
+    }
+}
+
+/// Formats the `UUID` int array of a player or entity the way Minecraft names player files.
+pub fn uuid(root: &NBTValue) -> Option<String> {
+    let compound = match &root.ty {
+        ValueType::Compound(compound) => compound,
+        _ => return None,
+    };
+    let uuid = match &compound.get("UUID")?.ty {
+        ValueType::IntArray(uuid) if uuid.len() == 4 => uuid,
+        _ => return None,
+    };
+    let hex: String = uuid.iter().map(|part| format!("{:08x}", part)).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+/// Reads the entries of a quarantine file, which is treated as empty if it doesn't exist yet.
+pub fn load(path: &Path) -> Result<Vec<QuarantineEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut root = file::read_file(path)?.root;
+    let compound = get_variant_mut!(root.ty, ValueType::Compound);
+    let items = match compound.shift_remove("Items") {
+        Some(NBTValue {
+            ty: ValueType::List(items),
+            ..
+        }) => items,
+        _ => bail!("Quarantine file has no Items list"),
+    };
+    items.into_iter().map(QuarantineEntry::from_nbt).collect()
+}
+
+/// Replaces the entries of a quarantine file.
+pub fn save(path: &Path, entries: Vec<QuarantineEntry>, options: &WriteOptions) -> Result<()> {
+    let items = entries.into_iter().map(QuarantineEntry::into_nbt).collect();
+    let mut compound = Compound::new();
+    compound.insert("Items".to_string(), NBTValue::new(ValueType::List(items)));
+    let root = NBTValue::new(ValueType::Compound(compound));
+    file::write_file(path, &NBTFile::new(root), options)
+}
+
+/// Adds entries to a quarantine file, creating it if needed.
+pub fn append(path: &Path, entries: Vec<QuarantineEntry>, options: &WriteOptions) -> Result<()> {
+    let mut existing = load(path)?;
+    existing.extend(entries);
+    save(path, existing, options)
+}
+
+/// Puts a quarantined item back into one of a player's item lists. The item goes into `slot`,
+/// or the slot it was taken from if `None`.
+pub fn restore(
+    root: &mut NBTValue,
+    entry: QuarantineEntry,
+    list: ItemList,
+    slot: Option<i8>,
+) -> Result<()> {
+    let slot = match (slot, &entry.nested) {
+        (Some(slot), _) => slot,
+        (None, None) => entry
+            .slot
+            .context("Quarantined item has no slot, pick one")?,
+        (None, Some(_)) => bail!("Item was inside a container, pick a slot to restore it to"),
+    };
+    let taken = inventory::rank_player(root)?
+        .iter()
+        .any(|item| item.list == list && item.slot == Some(slot));
+    if taken {
+        bail!("Slot {} is already taken", slot);
+    }
+
+    let mut item = entry.item;
+    let compound = get_variant_mut!(item.ty, ValueType::Compound);
+    compound.insert("Slot".to_string(), NBTValue::new(ValueType::Byte(slot)));
+
+    let player = get_variant_mut!(root.ty, ValueType::Compound);
+    let items = player
+        .entry(list.tag_name().to_string())
+        .or_insert_with(|| NBTValue::new(ValueType::List(List::default())));
+    get_variant_mut!(items.ty, ValueType::List).push(item);
+    Ok(())
+}
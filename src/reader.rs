@@ -0,0 +1,188 @@
+use crate::{mutf8, Compound, List, NBTValue, ValueType};
+use anyhow::{anyhow, bail, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{Cursor, Read};
+
+pub struct NBTReader {
+    buffer: Cursor<Vec<u8>>,
+}
+
+impl NBTReader {
+    const READ_FNS: &'static [fn(&mut Self) -> Result<ValueType>] = &[
+        Self::read_zero,
+        Self::read_byte,
+        Self::read_short,
+        Self::read_int,
+        Self::read_long,
+        Self::read_float,
+        Self::read_double,
+        Self::read_byte_array,
+        Self::read_string,
+        Self::read_list,
+        Self::read_compound,
+        Self::read_int_array,
+        Self::read_long_array,
+    ];
+
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            buffer: Cursor::new(data),
+        }
+    }
+
+    /// Reads the root compound that makes up the whole buffer, returning its name along with
+    /// it. The name is `None` for the nameless root network NBT uses since 1.20.2.
+    pub fn read_root(&mut self) -> Result<(Option<String>, NBTValue)> {
+        let type_id = self.buffer.read_u8()?;
+        if type_id != 10 {
+            bail!(
+                "Expected a compound as the root tag, found tag type {}",
+                type_id
+            );
+        }
+        let payload_start = self.buffer.position();
+
+        let named_error = match self.read_named_root() {
+            Ok((name, root)) => match self.remaining() {
+                0 => return Ok((Some(name), root)),
+                remaining => anyhow!(
+                    "{} bytes of trailing data after the root compound",
+                    remaining
+                ),
+            },
+            Err(err) => err,
+        };
+        // The named form failing to line up with the data may mean there is no name
+        self.buffer.set_position(payload_start);
+        if let Ok(root) = self.read_value(10) {
+            if self.remaining() == 0 {
+                return Ok((None, root));
+            }
+        }
+        Err(named_error)
+    }
+
+    fn read_named_root(&mut self) -> Result<(String, NBTValue)> {
+        let name = self.read_name()?;
+        Ok((name, self.read_value(10)?))
+    }
+
+    fn remaining(&self) -> usize {
+        self.buffer.get_ref().len() - self.buffer.position() as usize
+    }
+
+    fn read_value(&mut self, type_id: usize) -> Result<NBTValue> {
+        let reader = match Self::READ_FNS.get(type_id) {
+            Some(reader) if type_id != 0 => *reader,
+            _ => bail!("Invalid tag type {}", type_id),
+        };
+        let start = self.buffer.position() as usize;
+        let inner = reader(self)?;
+        let end = self.buffer.position() as usize;
+        Ok(NBTValue {
+            start,
+            end,
+            ty: inner,
+        })
+    }
+
+    fn read_zero(&mut self) -> Result<ValueType> {
+        unreachable!("Tried to read value with type id 0");
+    }
+
+    fn read_byte(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Byte(self.buffer.read_i8()?))
+    }
+
+    fn read_short(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Short(self.buffer.read_i16::<BigEndian>()?))
+    }
+
+    fn read_int(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Int(self.buffer.read_i32::<BigEndian>()?))
+    }
+
+    fn read_long(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Long(self.buffer.read_i64::<BigEndian>()?))
+    }
+
+    fn read_float(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Float(self.buffer.read_f32::<BigEndian>()?))
+    }
+
+    fn read_double(&mut self) -> Result<ValueType> {
+        Ok(ValueType::Double(self.buffer.read_f64::<BigEndian>()?))
+    }
+
+    fn read_byte_array(&mut self) -> Result<ValueType> {
+        let length = self.buffer.read_i32::<BigEndian>()?;
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(self.buffer.read_i8()?);
+        }
+        Ok(ValueType::ByteArray(items))
+    }
+
+    fn read_string(&mut self) -> Result<ValueType> {
+        let length = self.buffer.read_u16::<BigEndian>()?;
+        let mut bytes = vec![0; length as usize];
+        self.buffer.read_exact(&mut bytes)?;
+        Ok(ValueType::String(mutf8::decode(&bytes)?))
+    }
+
+    fn read_name(&mut self) -> Result<String> {
+        let length = self.buffer.read_u16::<BigEndian>()?;
+        let mut bytes = vec![0; length as usize];
+        self.buffer.read_exact(&mut bytes)?;
+        mutf8::decode(&bytes)
+    }
+
+    fn read_list(&mut self) -> Result<ValueType> {
+        let element_type = self.buffer.read_u8()?;
+        let length = self.buffer.read_i32::<BigEndian>()?;
+        if element_type == 0 && length > 0 {
+            bail!("List of {} elements has no element type", length);
+        }
+        if element_type as usize >= Self::READ_FNS.len() {
+            bail!("Invalid tag type {}", element_type);
+        }
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(self.read_value(element_type as usize)?);
+        }
+        Ok(ValueType::List(List {
+            element_type,
+            items,
+        }))
+    }
+
+    fn read_compound(&mut self) -> Result<ValueType> {
+        let mut compound = Compound::new();
+        loop {
+            let type_id = self.buffer.read_u8()? as usize;
+            if type_id == 0 {
+                return Ok(ValueType::Compound(compound));
+            }
+            let name = self.read_name()?;
+            compound.insert(name, self.read_value(type_id)?);
+        }
+    }
+
+    fn read_int_array(&mut self) -> Result<ValueType> {
+        let length = self.buffer.read_i32::<BigEndian>()?;
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(self.buffer.read_i32::<BigEndian>()?);
+        }
+        Ok(ValueType::IntArray(items))
+    }
+
+    fn read_long_array(&mut self) -> Result<ValueType> {
+        let length = self.buffer.read_i32::<BigEndian>()?;
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(self.buffer.read_i64::<BigEndian>()?);
+        }
+        Ok(ValueType::LongArray(items))
+    }
+}
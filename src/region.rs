@@ -0,0 +1,596 @@
+//! Anvil region files (`r.<x>.<z>.mca`), which store the chunks of a 32x32 chunk area. The
+//! file starts with a table of 1024 chunk locations followed by a table of 1024 timestamps,
+//! each 4KiB long. A location is a 3 byte sector offset and a 1 byte sector count, and the
+//! chunk stored there is prefixed with its length and compression type.
+//!
+//! Chunks that don't fit in 255 sectors are stored in a `c.<x>.<z>.mcc` file next to the
+//! region instead, marked by the 0x80 bit of the compression type.
+
+use crate::file::{self, Compression, WriteOptions};
+use crate::{get_variant, get_variant_mut, lz4, Compound, NBTValue, ValueType};
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Reverse;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+pub const SECTOR_SIZE: usize = 4096;
+const HEADER_SECTORS: usize = 2;
+const MAX_CHUNK_SECTORS: usize = 255;
+const EXTERNAL_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkCompression {
+    Gzip,
+    Zlib,
+    None,
+    Lz4,
+}
+
+impl ChunkCompression {
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(ChunkCompression::Gzip),
+            2 => Ok(ChunkCompression::Zlib),
+            3 => Ok(ChunkCompression::None),
+            4 => Ok(ChunkCompression::Lz4),
+            127 => bail!("Chunks with custom compression are not supported"),
+            _ => bail!("Unknown chunk compression type {}", id),
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            ChunkCompression::Gzip => 1,
+            ChunkCompression::Zlib => 2,
+            ChunkCompression::None => 3,
+            ChunkCompression::Lz4 => 4,
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            ChunkCompression::Gzip => Compression::Gzip.decompress(data),
+            ChunkCompression::Zlib => Compression::Zlib.decompress(data),
+            ChunkCompression::None => Compression::None.decompress(data),
+            ChunkCompression::Lz4 => lz4::decompress(data),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            ChunkCompression::Gzip => Compression::Gzip.compress(data),
+            ChunkCompression::Zlib => Compression::Zlib.compress(data),
+            ChunkCompression::None => Compression::None.compress(data),
+            ChunkCompression::Lz4 => lz4::compress(data),
+        }
+    }
+}
+
+pub struct Chunk {
+    /// Chunk coordinates in the world
+    pub x: i32,
+    pub z: i32,
+    /// When the chunk was last saved, in seconds since the unix epoch
+    pub timestamp: u32,
+    pub compression: ChunkCompression,
+    /// Whether the chunk was read from an external `.mcc` file
+    pub external: bool,
+    pub root: NBTValue,
+}
+
+/// A chunk that couldn't be read. Its sectors are written back as they were so that nothing is
+/// lost, and its external file, if it has one, is left alone.
+pub struct CorruptChunk {
+    pub x: i32,
+    pub z: i32,
+    pub timestamp: u32,
+    /// The chunk's sectors, or nothing if its location was in the header or past the end of the
+    /// file
+    pub data: Vec<u8>,
+    pub error: anyhow::Error,
+}
+
+/// Compressed data of a chunk too large to be stored in the region file.
+pub struct ExternalChunk {
+    pub x: i32,
+    pub z: i32,
+    pub data: Vec<u8>,
+}
+
+/// Path of the file an oversized chunk is stored in, next to its region file.
+pub fn external_path(region_path: &Path, x: i32, z: i32) -> PathBuf {
+    region_path.with_file_name(format!("c.{}.{}.mcc", x, z))
+}
+
+/// Position of the chunk in the region's header tables.
+fn header_index(x: i32, z: i32) -> usize {
+    (x.rem_euclid(32) + z.rem_euclid(32) * 32) as usize
+}
+
+pub struct Region {
+    /// Region coordinates in the world
+    pub x: i32,
+    pub z: i32,
+    pub chunks: Vec<Chunk>,
+    /// Chunks that couldn't be read, which are kept as they are
+    pub corrupt: Vec<CorruptChunk>,
+}
+
+/// Reads the region coordinates from a file name like `r.<x>.<z>.mca`.
+pub fn region_coords(path: &Path) -> Result<(i32, i32)> {
+    let name = path.file_name().and_then(|name| name.to_str());
+    let coords = name.and_then(|name| {
+        let mut parts = name.strip_prefix("r.")?.strip_suffix(".mca")?.split('.');
+        let x = parts.next()?.parse().ok()?;
+        let z = parts.next()?.parse().ok()?;
+        match parts.next() {
+            Some(_) => None,
+            None => Some((x, z)),
+        }
+    });
+    coords.context("Region file names must look like r.<x>.<z>.mca")
+}
+
+impl Region {
+    /// Reads a region file along with any external chunk files next to it.
+    pub fn read(path: &Path) -> Result<Region> {
+        let (x, z) = region_coords(path)?;
+        Region::parse(&std::fs::read(path)?, x, z, Some(path))
+    }
+
+    /// Parses region data. Chunks stored in external files can't be read this way and end up
+    /// in `corrupt`.
+    pub fn from_bytes(data: &[u8], region_x: i32, region_z: i32) -> Result<Region> {
+        Region::parse(data, region_x, region_z, None)
+    }
+
+    fn parse(data: &[u8], region_x: i32, region_z: i32, path: Option<&Path>) -> Result<Region> {
+        if data.len() < HEADER_SECTORS * SECTOR_SIZE {
+            bail!("Region file is too short to have a header");
+        }
+        let mut header = Cursor::new(&data[..HEADER_SECTORS * SECTOR_SIZE]);
+        let mut locations = Vec::with_capacity(1024);
+        for _ in 0..1024 {
+            locations.push(header.read_u32::<BigEndian>()?);
+        }
+        let mut timestamps = Vec::with_capacity(1024);
+        for _ in 0..1024 {
+            timestamps.push(header.read_u32::<BigEndian>()?);
+        }
+
+        let mut chunks = Vec::new();
+        let mut corrupt = Vec::new();
+        for (index, (&location, &timestamp)) in locations.iter().zip(&timestamps).enumerate() {
+            if location == 0 {
+                continue;
+            }
+            let x = region_x * 32 + (index % 32) as i32;
+            let z = region_z * 32 + (index / 32) as i32;
+            let offset = (location >> 8) as usize * SECTOR_SIZE;
+            let sectors = (location & 0xff) as usize;
+            let result = read_chunk(data, offset, sectors, || {
+                let path = path.context("Chunk is stored in an external file")?;
+                let external_path = external_path(path, x, z);
+                std::fs::read(&external_path)
+                    .with_context(|| format!("Failed to read {}", external_path.display()))
+            });
+            let (compression, external, root) = match result {
+                Ok(chunk) => chunk,
+                Err(error) => {
+                    // Sectors inside the header aren't the chunk's, so nothing is kept of them
+                    let kept = if offset < HEADER_SECTORS * SECTOR_SIZE {
+                        Vec::new()
+                    } else {
+                        let start = offset.min(data.len());
+                        let end = (offset + sectors * SECTOR_SIZE).min(data.len());
+                        data[start..end].to_vec()
+                    };
+                    corrupt.push(CorruptChunk {
+                        x,
+                        z,
+                        timestamp,
+                        data: kept,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            chunks.push(Chunk {
+                x,
+                z,
+                timestamp,
+                compression,
+                external,
+                root,
+            });
+        }
+        Ok(Region {
+            x: region_x,
+            z: region_z,
+            chunks,
+            corrupt,
+        })
+    }
+
+    /// Serializes the region, laying the chunks out one after another from the first sector
+    /// after the header. Chunks too large for the region file are returned separately and
+    /// only get a stub in the region.
+    pub fn to_bytes(&self) -> Result<(Vec<u8>, Vec<ExternalChunk>)> {
+        let mut locations = vec![0u32; 1024];
+        let mut timestamps = vec![0u32; 1024];
+        let mut body = Vec::new();
+        let mut external = Vec::new();
+        for chunk in &self.chunks {
+            let index = header_index(chunk.x, chunk.z);
+            let data = chunk.compression.compress(&crate::to_bytes(&chunk.root)?)?;
+            let offset = HEADER_SECTORS + body.len() / SECTOR_SIZE;
+            timestamps[index] = chunk.timestamp;
+
+            if (data.len() + 5).div_ceil(SECTOR_SIZE) > MAX_CHUNK_SECTORS {
+                body.write_u32::<BigEndian>(1)?;
+                body.write_u8(chunk.compression.id() | EXTERNAL_FLAG)?;
+                external.push(ExternalChunk {
+                    x: chunk.x,
+                    z: chunk.z,
+                    data,
+                });
+            } else {
+                body.write_u32::<BigEndian>(data.len() as u32 + 1)?;
+                body.write_u8(chunk.compression.id())?;
+                body.extend_from_slice(&data);
+            }
+            body.resize(body.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+            let sectors = HEADER_SECTORS + body.len() / SECTOR_SIZE - offset;
+            locations[index] = (offset as u32) << 8 | sectors as u32;
+        }
+
+        for chunk in &self.corrupt {
+            if chunk.data.is_empty() {
+                continue;
+            }
+            let index = header_index(chunk.x, chunk.z);
+            let offset = HEADER_SECTORS + body.len() / SECTOR_SIZE;
+            body.extend_from_slice(&chunk.data);
+            body.resize(body.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+            let sectors = HEADER_SECTORS + body.len() / SECTOR_SIZE - offset;
+            locations[index] = (offset as u32) << 8 | sectors as u32;
+            timestamps[index] = chunk.timestamp;
+        }
+
+        let mut region = Vec::with_capacity(HEADER_SECTORS * SECTOR_SIZE + body.len());
+        for location in locations {
+            region.write_u32::<BigEndian>(location)?;
+        }
+        for timestamp in timestamps {
+            region.write_u32::<BigEndian>(timestamp)?;
+        }
+        region.extend_from_slice(&body);
+        Ok((region, external))
+    }
+
+    /// Writes the region and its external chunk files. External files of chunks that fit in
+    /// the region again are deleted, and the coordinates of those chunks are returned.
+    pub fn write(&self, path: &Path, options: &WriteOptions) -> Result<Vec<(i32, i32)>> {
+        let (region, external) = self.to_bytes()?;
+        for chunk in &external {
+            file::write_atomic(&external_path(path, chunk.x, chunk.z), &chunk.data, options)?;
+        }
+        file::write_atomic(path, &region, options)?;
+
+        let mut folded = Vec::new();
+        for chunk in self.chunks.iter().filter(|chunk| chunk.external) {
+            if external.iter().any(|e| (e.x, e.z) == (chunk.x, chunk.z)) {
+                continue;
+            }
+            let external_path = external_path(path, chunk.x, chunk.z);
+            if external_path.exists() {
+                file::remove(&external_path, options)?;
+            }
+            folded.push((chunk.x, chunk.z));
+        }
+        Ok(folded)
+    }
+
+    pub fn chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.x == x && chunk.z == z)
+    }
+
+    pub fn chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut Chunk> {
+        self.chunks
+            .iter_mut()
+            .find(|chunk| chunk.x == x && chunk.z == z)
+    }
+}
+
+/// Reads the chunk at `offset`, calling `read_external` for the contents of its external file
+/// if it has one.
+fn read_chunk(
+    data: &[u8],
+    offset: usize,
+    sectors: usize,
+    read_external: impl FnOnce() -> Result<Vec<u8>>,
+) -> Result<(ChunkCompression, bool, NBTValue)> {
+    if offset < HEADER_SECTORS * SECTOR_SIZE || offset + 5 > data.len() {
+        bail!("Chunk location is outside of the file");
+    }
+    let mut cursor = Cursor::new(&data[offset..]);
+    let length = cursor.read_u32::<BigEndian>()? as usize;
+    let compression_id = cursor.read_u8()?;
+    let compression = ChunkCompression::from_id(compression_id & !EXTERNAL_FLAG)?;
+    if length == 0 || length + 4 > sectors * SECTOR_SIZE || offset + 4 + length > data.len() {
+        bail!("Chunk length {} doesn't fit in its sectors", length);
+    }
+    let compressed = &data[offset + 5..offset + 4 + length];
+
+    let external = compression_id & EXTERNAL_FLAG != 0;
+    let decompressed = if external {
+        compression.decompress(&read_external()?)?
+    } else {
+        compression.decompress(compressed)?
+    };
+    Ok((compression, external, crate::from_bytes(decompressed)?))
+}
+
+pub struct BlockEntityEntry {
+    /// Position of the entry in the chunk's block entity list
+    pub index: usize,
+    pub id: String,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub size: usize,
+}
+
+/// Returns the block entity list of a chunk, which moved from `Level.TileEntities` to
+/// `block_entities` in 1.18.
+pub fn block_entities(chunk: &NBTValue) -> Result<Option<&Vec<NBTValue>>> {
+    let root = get_variant!(chunk.ty, ValueType::Compound);
+    let list = match root.get("block_entities") {
+        Some(list) => list,
+        None => match root.get("Level") {
+            Some(level) => match get_variant!(level.ty, ValueType::Compound).get("TileEntities") {
+                Some(list) => list,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(get_variant!(list.ty, ValueType::List)))
+}
+
+pub fn block_entities_mut(chunk: &mut NBTValue) -> Result<Option<&mut Vec<NBTValue>>> {
+    let root = get_variant_mut!(chunk.ty, ValueType::Compound);
+    let list = if root.contains_key("block_entities") {
+        root.get_mut("block_entities").unwrap()
+    } else {
+        match root.get_mut("Level") {
+            Some(level) => {
+                match get_variant_mut!(level.ty, ValueType::Compound).get_mut("TileEntities") {
+                    Some(list) => list,
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        }
+    };
+    Ok(Some(get_variant_mut!(list.ty, ValueType::List)))
+}
+
+/// Reads one of the `x`, `y` and `z` tags holding a block entity's position.
+fn coordinate(compound: &Compound, axis: &str) -> Result<i32> {
+    let value = compound.get(axis).context("Block entity has no position")?;
+    Ok(*get_variant!(value.ty, ValueType::Int))
+}
+
+/// Collects the block entities of a chunk, ranked from largest to smallest.
+pub fn rank_block_entities(chunk: &NBTValue) -> Result<Vec<BlockEntityEntry>> {
+    let list = match block_entities(chunk)? {
+        Some(list) => list,
+        None => return Ok(Vec::new()),
+    };
+    let mut entries = Vec::with_capacity(list.len());
+    for (index, entry) in list.iter().enumerate() {
+        let compound = get_variant!(entry.ty, ValueType::Compound);
+        let id = match compound.get("id") {
+            Some(id) => get_variant!(id.ty, ValueType::String).clone(),
+            None => "unknown".to_string(),
+        };
+        entries.push(BlockEntityEntry {
+            index,
+            id,
+            x: coordinate(compound, "x")?,
+            y: coordinate(compound, "y")?,
+            z: coordinate(compound, "z")?,
+            size: entry.size(),
+        });
+    }
+    entries.sort_by_key(|entry| Reverse(entry.size));
+    Ok(entries)
+}
+
+fn block_entity_index(chunk: &NBTValue, x: i32, y: i32, z: i32) -> Result<usize> {
+    let entries = rank_block_entities(chunk)?;
+    let entry = entries
+        .iter()
+        .find(|entry| (entry.x, entry.y, entry.z) == (x, y, z))
+        .with_context(|| format!("No block entity at {},{},{}", x, y, z))?;
+    Ok(entry.index)
+}
+
+/// Removes the block entity at the given block position from its chunk.
+pub fn remove_block_entity(region: &mut Region, x: i32, y: i32, z: i32) -> Result<NBTValue> {
+    let chunk = region
+        .chunk_mut(x >> 4, z >> 4)
+        .with_context(|| format!("Chunk {},{} is not in the region", x >> 4, z >> 4))?;
+    let index = block_entity_index(&chunk.root, x, y, z)?;
+    let list = block_entities_mut(&mut chunk.root)?.unwrap();
+    Ok(list.remove(index))
+}
+
+/// Removes the item in `slot` of the container block entity at the given block position.
+pub fn remove_block_entity_item(
+    region: &mut Region,
+    x: i32,
+    y: i32,
+    z: i32,
+    slot: i8,
+) -> Result<NBTValue> {
+    let chunk = region
+        .chunk_mut(x >> 4, z >> 4)
+        .with_context(|| format!("Chunk {},{} is not in the region", x >> 4, z >> 4))?;
+    let index = block_entity_index(&chunk.root, x, y, z)?;
+    let block_entity = &mut block_entities_mut(&mut chunk.root)?.unwrap()[index];
+    let compound = get_variant_mut!(block_entity.ty, ValueType::Compound);
+    let items = compound
+        .get_mut("Items")
+        .context("Block entity has no items")?;
+    let items = get_variant_mut!(items.ty, ValueType::List);
+    let position = items
+        .iter()
+        .position(|item| match &item.ty {
+            ValueType::Compound(item) => {
+                matches!(item.get("Slot").map(|slot| &slot.ty), Some(ValueType::Byte(s)) if *s == slot)
+            }
+            _ => false,
+        })
+        .with_context(|| format!("No item in slot {}", slot))?;
+    Ok(items.remove(position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compound;
+
+    fn chunk(x: i32, z: i32, compression: ChunkCompression, payload: Vec<i8>) -> Chunk {
+        let mut compound = Compound::new();
+        compound.insert("xPos".to_string(), NBTValue::new(ValueType::Int(x)));
+        compound.insert("zPos".to_string(), NBTValue::new(ValueType::Int(z)));
+        compound.insert(
+            "Payload".to_string(),
+            NBTValue::new(ValueType::ByteArray(payload)),
+        );
+        Chunk {
+            x,
+            z,
+            timestamp: 1_600_000_000 + (x + 32) as u32,
+            compression,
+            external: false,
+            root: NBTValue::new(ValueType::Compound(compound)),
+        }
+    }
+
+    fn region(chunks: Vec<Chunk>) -> Region {
+        Region {
+            x: -1,
+            z: 0,
+            chunks,
+            corrupt: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // In header order, which is the order chunks are read in
+        let original = region(vec![
+            chunk(-32, 0, ChunkCompression::Zlib, vec![1; 100]),
+            chunk(-20, 5, ChunkCompression::Lz4, vec![3; 5000]),
+            chunk(-10, 7, ChunkCompression::None, vec![4; 5000]),
+            chunk(-1, 31, ChunkCompression::Gzip, vec![2; 10_000]),
+        ]);
+        let (data, external) = original.to_bytes().unwrap();
+        assert!(external.is_empty());
+        assert_eq!(data.len() % SECTOR_SIZE, 0);
+
+        let parsed = Region::from_bytes(&data, -1, 0).unwrap();
+        assert!(parsed.corrupt.is_empty());
+        assert_eq!(parsed.chunks.len(), original.chunks.len());
+        for chunk in &original.chunks {
+            let read = parsed.chunk(chunk.x, chunk.z).unwrap();
+            assert_eq!(read.timestamp, chunk.timestamp);
+            assert_eq!(read.compression, chunk.compression);
+            assert_eq!(
+                crate::to_bytes(&read.root).unwrap(),
+                crate::to_bytes(&chunk.root).unwrap()
+            );
+        }
+        assert_eq!(parsed.to_bytes().unwrap().0, data);
+    }
+
+    #[test]
+    fn oversized_chunk_gets_external_stub() {
+        let payload = vec![5; (MAX_CHUNK_SECTORS + 1) * SECTOR_SIZE];
+        let original = region(vec![chunk(-5, 3, ChunkCompression::None, payload)]);
+        let (data, external) = original.to_bytes().unwrap();
+        assert_eq!(data.len(), (HEADER_SECTORS + 1) * SECTOR_SIZE);
+        assert_eq!(external.len(), 1);
+        assert_eq!((external[0].x, external[0].z), (-5, 3));
+
+        // The stub is a length of 1 and the compression type with the external flag
+        let stub = &data[HEADER_SECTORS * SECTOR_SIZE..][..5];
+        assert_eq!(
+            stub,
+            &[0, 0, 0, 1, ChunkCompression::None.id() | EXTERNAL_FLAG]
+        );
+        let (compression, is_external, root) =
+            read_chunk(&data, HEADER_SECTORS * SECTOR_SIZE, 1, || {
+                Ok(external[0].data.clone())
+            })
+            .unwrap();
+        assert_eq!(compression, ChunkCompression::None);
+        assert!(is_external);
+        assert_eq!(
+            crate::to_bytes(&root).unwrap(),
+            crate::to_bytes(&original.chunks[0].root).unwrap()
+        );
+
+        // Without a path to find the external file next to, the chunk can't be read
+        let parsed = Region::from_bytes(&data, -1, 0).unwrap();
+        assert!(parsed.chunks.is_empty());
+        assert_eq!(parsed.corrupt.len(), 1);
+    }
+
+    #[test]
+    fn corrupt_chunk_is_kept() {
+        let original = region(vec![
+            chunk(-32, 0, ChunkCompression::Zlib, vec![1; 100]),
+            chunk(-31, 0, ChunkCompression::Zlib, vec![2; 100]),
+        ]);
+        let (mut data, _) = original.to_bytes().unwrap();
+        let sector = HEADER_SECTORS * SECTOR_SIZE;
+        // Break the first chunk's compressed data
+        data[sector + 5..sector + 10].copy_from_slice(&[0xff; 5]);
+
+        let parsed = Region::from_bytes(&data, -1, 0).unwrap();
+        assert_eq!(parsed.chunks.len(), 1);
+        assert_eq!(parsed.corrupt.len(), 1);
+        let corrupt = &parsed.corrupt[0];
+        assert_eq!((corrupt.x, corrupt.z), (-32, 0));
+        assert_eq!(corrupt.data, data[sector..sector + SECTOR_SIZE]);
+
+        let (rewritten, _) = parsed.to_bytes().unwrap();
+        let reparsed = Region::from_bytes(&rewritten, -1, 0).unwrap();
+        assert_eq!(reparsed.corrupt[0].data, corrupt.data);
+        assert_eq!(reparsed.corrupt[0].timestamp, corrupt.timestamp);
+    }
+
+    #[test]
+    fn chunk_located_in_header_is_dropped() {
+        let original = region(vec![chunk(-32, 0, ChunkCompression::Zlib, vec![1; 100])]);
+        let (mut data, _) = original.to_bytes().unwrap();
+        // Point the second chunk of the header at sector 1 with a length reaching into the body
+        data[4..8].copy_from_slice(&[0, 0, 1, 2]);
+
+        let parsed = Region::from_bytes(&data, -1, 0).unwrap();
+        assert_eq!(parsed.chunks.len(), 1);
+        assert_eq!(parsed.corrupt.len(), 1);
+        assert!(parsed.corrupt[0].data.is_empty());
+
+        // Nothing of the valid chunk is copied to the broken chunk's position
+        let (rewritten, _) = parsed.to_bytes().unwrap();
+        assert_eq!(rewritten, original.to_bytes().unwrap().0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Item rankings of player files as JSON or CSV, for dashboards and other tools.
+
+use crate::file;
+use crate::inventory::{self, ItemEntry};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// The items of one player file, ranked from largest to smallest.
+pub struct Ranking {
+    /// The combined size of the player's item lists
+    pub total_size: usize,
+    pub items: Vec<ItemEntry>,
+}
+
+impl Ranking {
+    pub fn read(path: &Path) -> Result<Ranking> {
+        let nbt_file = file::read_file(path)?;
+        let player = inventory::player_data(&nbt_file.root);
+        Ok(Ranking {
+            total_size: inventory::total_size(player)?,
+            items: inventory::rank_player(player)?,
+        })
+    }
+
+    /// How much of the player's total size an item takes up, in percent.
+    pub fn percentage(&self, item: &ItemEntry) -> f64 {
+        item.size as f64 * 100.0 / self.total_size.max(1) as f64
+    }
+}
+
+/// Ranks the items of every file, keeping the error of each file that can't be read instead of
+/// giving up on the rest.
+pub fn ranking(files: &[PathBuf]) -> Vec<(PathBuf, Result<Ranking>)> {
+    files
+        .iter()
+        .map(|path| (path.clone(), Ranking::read(path)))
+        .collect()
+}
+
+/// Builds the JSON report of a ranking, where files that couldn't be read have an `error`
+/// instead of their items.
+pub fn to_json(rankings: &[(PathBuf, Result<Ranking>)]) -> Value {
+    let mut players = Vec::with_capacity(rankings.len());
+    let mut total = 0;
+    for (path, ranking) in rankings {
+        let name = path.to_string_lossy();
+        let ranking = match ranking {
+            Ok(ranking) => ranking,
+            Err(err) => {
+                players.push(json!({
+                    "file": name,
+                    "error": format!("{:#}", err),
+                }));
+                continue;
+            }
+        };
+        total += ranking.total_size;
+        let items: Vec<Value> = ranking
+            .items
+            .iter()
+            .map(|item| {
+                json!({
+                    "list": item.list.tag_name(),
+                    "index": item.index,
+                    "slot": item.slot,
+                    "id": item.id,
+                    "count": item.count,
+                    "size": item.size,
+                    "percentage": ranking.percentage(item),
+                })
+            })
+            .collect();
+        players.push(json!({
+            "file": name,
+            "total_size": ranking.total_size,
+            "items": items,
+        }));
+    }
+    json!({
+        "players": players,
+        "total_size": total,
+    })
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Builds the CSV report of a ranking, with a row for every item and a total row for every
+/// file. Files that couldn't be read are left out.
+pub fn to_csv(rankings: &[(PathBuf, Result<Ranking>)]) -> String {
+    let mut csv = String::from("file,list,index,slot,id,count,size,percentage\n");
+    for (path, ranking) in rankings {
+        let ranking = match ranking {
+            Ok(ranking) => ranking,
+            Err(_) => continue,
+        };
+        let name = csv_field(&path.to_string_lossy());
+        let optional = |value: Option<i32>| value.map(|v| v.to_string()).unwrap_or_default();
+        for item in &ranking.items {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{:.2}\n",
+                name,
+                item.list.tag_name(),
+                item.index,
+                optional(item.slot.map(i32::from)),
+                csv_field(&item.id),
+                optional(item.count),
+                item.size,
+                ranking.percentage(item)
+            ));
+        }
+        csv.push_str(&format!(
+            "{},total,,,,,{},100.00\n",
+            name, ranking.total_size
+        ));
+    }
+    csv
+}
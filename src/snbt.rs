@@ -0,0 +1,562 @@
+//! Stringified NBT, the text format used by Minecraft commands, like
+//! `{id:"minecraft:stone",Count:1b}`.
+
+use crate::{Compound, List, NBTValue, ValueType};
+use anyhow::{bail, Result};
+use std::fmt::{Display, Write};
+
+fn is_bare_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-.+".contains(c)
+}
+
+/// How to lay out and shorten formatted SNBT. The default writes everything on one line in full,
+/// which can be parsed back into the same value.
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// Spaces to indent nested compounds and lists by, or `None` to keep everything on one line
+    pub indent: Option<usize>,
+    /// Longest string to write in full, longer ones are cut off
+    pub max_string: Option<usize>,
+    /// Most elements of a list or array to write, the rest are left out
+    pub max_elements: Option<usize>,
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    out: String,
+    depth: usize,
+}
+
+impl Formatter<'_> {
+    fn write_string(&mut self, s: &str) {
+        let (s, cut) = match self.options.max_string {
+            Some(max) if s.chars().count() > max => {
+                let end = s.char_indices().nth(max).map_or(s.len(), |(i, _)| i);
+                (&s[..end], s.chars().count() - max)
+            }
+            _ => (s, 0),
+        };
+        let quote = if s.contains('"') && !s.contains('\'') {
+            '\''
+        } else {
+            '"'
+        };
+        self.out.push(quote);
+        for c in s.chars() {
+            if c == quote || c == '\\' {
+                self.out.push('\\');
+            }
+            self.out.push(c);
+        }
+        self.out.push(quote);
+        if cut > 0 {
+            let _ = write!(self.out, "... (+{} characters)", cut);
+        }
+    }
+
+    fn write_key(&mut self, key: &str) {
+        if !key.is_empty() && key.chars().all(is_bare_char) {
+            self.out.push_str(key);
+        } else {
+            self.write_string(key);
+        }
+    }
+
+    /// Starts a line for an element of a compound or list when indenting.
+    fn new_line(&mut self) {
+        if let Some(indent) = self.options.indent {
+            self.out.push('\n');
+            self.out.push_str(&" ".repeat(indent * self.depth));
+        }
+    }
+
+    fn separator(&mut self) {
+        self.out.push(',');
+        if self.options.indent.is_some() {
+            self.out.push(' ');
+        }
+    }
+
+    /// Writes the note for the elements left out of a list or array.
+    fn write_cut(&mut self, len: usize) {
+        if let Some(max) = self.options.max_elements {
+            if len > max {
+                if max > 0 {
+                    self.separator();
+                }
+                let _ = write!(self.out, "... (+{} more)", len - max);
+            }
+        }
+    }
+
+    fn write_array<T: Display>(&mut self, prefix: &str, suffix: &str, values: &[T]) {
+        self.out.push('[');
+        self.out.push_str(prefix);
+        self.out.push(';');
+        let shown = self.options.max_elements.unwrap_or(values.len());
+        for (n, value) in values.iter().take(shown).enumerate() {
+            if n > 0 {
+                self.separator();
+            }
+            let _ = write!(self.out, "{}{}", value, suffix);
+        }
+        self.write_cut(values.len());
+        self.out.push(']');
+    }
+
+    fn write_list(&mut self, list: &[NBTValue]) {
+        // Lists of numbers and strings stay on one line
+        let nested = list
+            .iter()
+            .any(|element| matches!(element.ty, ValueType::List(_) | ValueType::Compound(_)));
+        let shown = self.options.max_elements.unwrap_or(list.len());
+        self.out.push('[');
+        self.depth += 1;
+        for (n, element) in list.iter().take(shown).enumerate() {
+            if n > 0 {
+                self.out.push(',');
+            }
+            if nested {
+                self.new_line();
+            } else if n > 0 && self.options.indent.is_some() {
+                self.out.push(' ');
+            }
+            self.write_value(element);
+        }
+        if list.len() > shown {
+            if nested {
+                if shown > 0 {
+                    self.out.push(',');
+                }
+                self.new_line();
+                let _ = write!(self.out, "... (+{} more)", list.len() - shown);
+            } else {
+                self.write_cut(list.len());
+            }
+        }
+        self.depth -= 1;
+        if nested {
+            self.new_line();
+        }
+        self.out.push(']');
+    }
+
+    fn write_compound(&mut self, compound: &Compound) {
+        self.out.push('{');
+        self.depth += 1;
+        for (n, (key, value)) in compound.iter().enumerate() {
+            if n > 0 {
+                self.out.push(',');
+            }
+            self.new_line();
+            self.write_key(key);
+            self.out.push(':');
+            if self.options.indent.is_some() {
+                self.out.push(' ');
+            }
+            self.write_value(value);
+        }
+        self.depth -= 1;
+        if !compound.is_empty() {
+            self.new_line();
+        }
+        self.out.push('}');
+    }
+
+    fn write_value(&mut self, value: &NBTValue) {
+        match &value.ty {
+            ValueType::Byte(v) => {
+                let _ = write!(self.out, "{}b", v);
+            }
+            ValueType::Short(v) => {
+                let _ = write!(self.out, "{}s", v);
+            }
+            ValueType::Int(v) => {
+                let _ = write!(self.out, "{}", v);
+            }
+            ValueType::Long(v) => {
+                let _ = write!(self.out, "{}L", v);
+            }
+            ValueType::Float(v) => {
+                let _ = write!(self.out, "{}f", v);
+            }
+            ValueType::Double(v) => {
+                let _ = write!(self.out, "{}d", v);
+            }
+            ValueType::ByteArray(values) => self.write_array("B", "b", values),
+            ValueType::IntArray(values) => self.write_array("I", "", values),
+            ValueType::LongArray(values) => self.write_array("L", "L", values),
+            ValueType::String(s) => self.write_string(s),
+            ValueType::List(list) => self.write_list(list),
+            ValueType::Compound(compound) => self.write_compound(compound),
+        }
+    }
+}
+
+/// Formats a value as SNBT.
+pub fn format(value: &NBTValue, options: &FormatOptions) -> String {
+    let mut formatter = Formatter {
+        options,
+        out: String::new(),
+        depth: 0,
+    };
+    formatter.write_value(value);
+    formatter.out
+}
+
+/// Formats a value as SNBT on a single line, in full.
+pub fn to_snbt(value: &NBTValue) -> String {
+    format(value, &FormatOptions::default())
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T> {
+        let before = &self.input[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        bail!("{} at line {} column {}", message, line, column)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(expected) {
+            return self.error(&format!("Expected '{}'", expected));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Consumes `c` if it is the next character after any whitespace.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn read_quoted(&mut self) -> Result<String> {
+        let quote = self.peek().unwrap();
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("Unterminated string"),
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '\\' => match self.peek() {
+                    Some(escaped) if escaped == quote || escaped == '\\' => {
+                        s.push(escaped);
+                        self.pos += 1;
+                    }
+                    _ => return self.error("Invalid escape sequence"),
+                },
+                c if c == quote => return Ok(s),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn read_bare(&mut self) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !is_bare_char(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn read_key(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') | Some('\'') => self.read_quoted(),
+            _ => {
+                let key = self.read_bare();
+                if key.is_empty() {
+                    return self.error("Expected a key");
+                }
+                Ok(key.to_string())
+            }
+        }
+    }
+
+    fn read_value(&mut self) -> Result<NBTValue> {
+        self.skip_whitespace();
+        let ty = match self.peek() {
+            Some('{') => self.read_compound()?,
+            Some('[') => self.read_list()?,
+            Some('"') | Some('\'') => ValueType::String(self.read_quoted()?),
+            _ => {
+                let start = self.pos;
+                let token = self.read_bare();
+                if token.is_empty() {
+                    self.pos = start;
+                    return self.error("Expected a value");
+                }
+                parse_scalar(token)
+            }
+        };
+        Ok(NBTValue::new(ty))
+    }
+
+    fn read_compound(&mut self) -> Result<ValueType> {
+        self.expect('{')?;
+        let mut compound = Compound::new();
+        if self.eat('}') {
+            return Ok(ValueType::Compound(compound));
+        }
+        loop {
+            let key = self.read_key()?;
+            self.expect(':')?;
+            let value = self.read_value()?;
+            compound.insert(key, value);
+            if self.eat('}') {
+                return Ok(ValueType::Compound(compound));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn read_list(&mut self) -> Result<ValueType> {
+        self.expect('[')?;
+        let rest = &self.input[self.pos..];
+        let array_type = match rest.get(..2) {
+            Some(prefix) if prefix.ends_with(';') => Some(prefix.as_bytes()[0]),
+            _ => None,
+        };
+        if let Some(array_type) = array_type {
+            self.pos += 2;
+            return self.read_array(array_type);
+        }
+
+        let mut list = Vec::new();
+        if self.eat(']') {
+            return Ok(ValueType::List(List::new(list)));
+        }
+        loop {
+            let start = self.pos;
+            let value = self.read_value()?;
+            if let Some(first) = list.first() {
+                if value.ty.type_id() != first.ty.type_id() {
+                    self.pos = start;
+                    self.skip_whitespace();
+                    return self.error("List elements must all have the same type");
+                }
+            }
+            list.push(value);
+            if self.eat(']') {
+                return Ok(ValueType::List(List::new(list)));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn read_array(&mut self, array_type: u8) -> Result<ValueType> {
+        let mut values = Vec::new();
+        if !self.eat(']') {
+            loop {
+                let start = self.pos;
+                values.push((start, self.read_value()?));
+                if self.eat(']') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+        macro_rules! collect {
+            ($variant:ident, $element:ident, $name:expr) => {{
+                let mut array = Vec::with_capacity(values.len());
+                for (start, value) in values {
+                    match value.ty {
+                        ValueType::$element(v) => array.push(v),
+                        _ => {
+                            self.pos = start;
+                            self.skip_whitespace();
+                            return self.error(concat!("Expected a ", $name, " in the array"));
+                        }
+                    }
+                }
+                ValueType::$variant(array)
+            }};
+        }
+        Ok(match array_type {
+            b'B' => collect!(ByteArray, Byte, "byte"),
+            b'I' => collect!(IntArray, Int, "int"),
+            b'L' => collect!(LongArray, Long, "long"),
+            _ => return self.error("Unknown array type"),
+        })
+    }
+}
+
+/// Reads an unquoted token as a number if it looks like one, or as a string otherwise.
+fn parse_scalar(token: &str) -> ValueType {
+    match token {
+        "true" => return ValueType::Byte(1),
+        "false" => return ValueType::Byte(0),
+        _ => {}
+    }
+    let (number, suffix) = match token.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&token[..i], Some(c.to_ascii_lowercase())),
+        _ => (token, None),
+    };
+    let is_integer = {
+        let digits = number.strip_prefix(&['-', '+'][..]).unwrap_or(number);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+    let is_decimal = number.contains(&['.', 'e', 'E'][..]) || is_integer;
+    let parsed = match suffix {
+        Some('b') if is_integer => number.parse().ok().map(ValueType::Byte),
+        Some('s') if is_integer => number.parse().ok().map(ValueType::Short),
+        Some('l') if is_integer => number.parse().ok().map(ValueType::Long),
+        Some('f') if is_decimal => number.parse().ok().map(ValueType::Float),
+        Some('d') if is_decimal => number.parse().ok().map(ValueType::Double),
+        None if is_integer => number.parse().ok().map(ValueType::Int),
+        None if is_decimal => number.parse().ok().map(ValueType::Double),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| ValueType::String(token.to_string()))
+}
+
+/// Parses a single SNBT value, allowing surrounding whitespace.
+pub fn parse(input: &str) -> Result<NBTValue> {
+    let mut parser = Parser { input, pos: 0 };
+    let value = parser.read_value()?;
+    parser.skip_whitespace();
+    if parser.pos < input.len() {
+        return parser.error("Unexpected trailing data");
+    }
+    Ok(value)
+}
+
+/// Parses an SNBT value at the start of `input`, returning it along with the number of bytes it
+/// took up.
+pub fn parse_prefix(input: &str) -> Result<(NBTValue, usize)> {
+    let mut parser = Parser { input, pos: 0 };
+    let value = parser.read_value()?;
+    Ok((value, parser.pos))
+}
+
+/// Checks whether `value` has everything `pattern` has, the way Minecraft matches NBT in
+/// commands. Compounds may have extra entries, and every element of a pattern list has to match
+/// some element of the list.
+pub fn matches(pattern: &NBTValue, value: &NBTValue) -> bool {
+    match (&pattern.ty, &value.ty) {
+        (ValueType::Compound(pattern), ValueType::Compound(value)) => pattern
+            .iter()
+            .all(|(key, pattern)| value.get(key).is_some_and(|value| matches(pattern, value))),
+        (ValueType::List(pattern), ValueType::List(value)) => {
+            if pattern.is_empty() {
+                return value.is_empty();
+            }
+            pattern
+                .iter()
+                .all(|pattern| value.iter().any(|value| matches(pattern, value)))
+        }
+        (ValueType::Byte(a), ValueType::Byte(b)) => a == b,
+        (ValueType::Short(a), ValueType::Short(b)) => a == b,
+        (ValueType::Int(a), ValueType::Int(b)) => a == b,
+        (ValueType::Long(a), ValueType::Long(b)) => a == b,
+        (ValueType::Float(a), ValueType::Float(b)) => a == b,
+        (ValueType::Double(a), ValueType::Double(b)) => a == b,
+        (ValueType::String(a), ValueType::String(b)) => a == b,
+        (ValueType::ByteArray(a), ValueType::ByteArray(b)) => a == b,
+        (ValueType::IntArray(a), ValueType::IntArray(b)) => a == b,
+        (ValueType::LongArray(a), ValueType::LongArray(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let input = r#"{
+            id: "minecraft:chest", Count: 1b, "a key": 'it\'s',
+            Items: [{Slot: 0b, id: "minecraft:stone", tag: {Damage: 3s}}],
+            Pos: [1.5d, -2.0d, 3.25d], Rot: [90.0f, 0.0f],
+            Big: 12345678901L, Bytes: [B; 1b, -2b], Ints: [I; 1, 2], Longs: [L; 3L],
+            Empty: {}, None: [], Quote: "say \"hi\" \\o/"
+        }"#;
+        let value = parse(input).unwrap();
+        let formatted = to_snbt(&value);
+        let reparsed = parse(&formatted).unwrap();
+        assert_eq!(to_snbt(&reparsed), formatted);
+        assert_eq!(
+            crate::to_bytes(&reparsed).unwrap(),
+            crate::to_bytes(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn format_options() {
+        let value = parse(r#"{id:"minecraft:bundle",Items:[{id:a},{id:b},{id:c}],Ints:[I;1,2,3]}"#)
+            .unwrap();
+        let options = FormatOptions {
+            indent: Some(2),
+            max_string: Some(4),
+            max_elements: Some(2),
+        };
+        let expected = "{
+  id: \"mine\"... (+12 characters),
+  Items: [
+    {
+      id: \"a\"
+    },
+    {
+      id: \"b\"
+    },
+    ... (+1 more)
+  ],
+  Ints: [I;1, 2, ... (+1 more)]
+}";
+        assert_eq!(format(&value, &options), expected);
+    }
+
+    #[test]
+    fn scalars() {
+        let types: Vec<u8> = ["1b", "1s", "1", "1L", "1.5f", "1.5", "1e3", "true", "stone"]
+            .iter()
+            .map(|token| parse(token).unwrap().ty.type_id())
+            .collect();
+        assert_eq!(types, [1, 2, 3, 4, 5, 6, 6, 1, 8]);
+    }
+
+    #[test]
+    fn error_position() {
+        let err = parse("{\n  id: \"minecraft:stone\",\n  Count: ]\n}").unwrap_err();
+        assert!(err.to_string().ends_with("at line 3 column 10"), "{}", err);
+    }
+
+    #[test]
+    fn prefix() {
+        let (value, len) = parse_prefix("{Slot:0b}].tag").unwrap();
+        assert_eq!(len, 9);
+        assert_eq!(to_snbt(&value), "{Slot:0b}");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{get_variant, mutf8, List, NBTValue, ValueType};
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, WriteBytesExt};
+
+pub struct NBTWriter {
+    buffer: Vec<u8>,
+}
+
+impl NBTWriter {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// Serializes a root compound, the counterpart of `NBTReader::read_root`. A `None` name
+    /// writes the nameless root of network NBT.
+    pub fn write_root(mut self, name: Option<&str>, value: &NBTValue) -> Result<Vec<u8>> {
+        get_variant!(value.ty, ValueType::Compound);
+        self.buffer.write_u8(10)?;
+        if let Some(name) = name {
+            self.write_name(name)?;
+        }
+        self.write_value(&value.ty)?;
+        Ok(self.buffer)
+    }
+
+    /// Serializes a value on its own, without the type id and name it has inside a compound.
+    pub fn write_payload(mut self, value: &NBTValue) -> Result<Vec<u8>> {
+        self.write_value(&value.ty)?;
+        Ok(self.buffer)
+    }
+
+    fn write_value(&mut self, value: &ValueType) -> Result<()> {
+        match value {
+            ValueType::Byte(x) => self.buffer.write_i8(*x)?,
+            ValueType::Short(x) => self.buffer.write_i16::<BigEndian>(*x)?,
+            ValueType::Int(x) => self.buffer.write_i32::<BigEndian>(*x)?,
+            ValueType::Long(x) => self.buffer.write_i64::<BigEndian>(*x)?,
+            ValueType::Float(x) => self.buffer.write_f32::<BigEndian>(*x)?,
+            ValueType::Double(x) => self.buffer.write_f64::<BigEndian>(*x)?,
+            ValueType::ByteArray(items) => {
+                self.write_length(items.len())?;
+                for item in items {
+                    self.buffer.write_i8(*item)?;
+                }
+            }
+            ValueType::String(string) => self.write_name(string)?,
+            ValueType::List(list) => self.write_list(list)?,
+            ValueType::Compound(compound) => {
+                for (name, value) in compound {
+                    self.buffer.write_u8(value.ty.type_id())?;
+                    self.write_name(name)?;
+                    self.write_value(&value.ty)?;
+                }
+                self.buffer.write_u8(0)?;
+            }
+            ValueType::IntArray(items) => {
+                self.write_length(items.len())?;
+                for item in items {
+                    self.buffer.write_i32::<BigEndian>(*item)?;
+                }
+            }
+            ValueType::LongArray(items) => {
+                self.write_length(items.len())?;
+                for item in items {
+                    self.buffer.write_i64::<BigEndian>(*item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_length(&mut self, length: usize) -> Result<()> {
+        if length > i32::MAX as usize {
+            bail!("Array or list of length {} is too long", length);
+        }
+        self.buffer.write_i32::<BigEndian>(length as i32)?;
+        Ok(())
+    }
+
+    fn write_name(&mut self, name: &str) -> Result<()> {
+        let bytes = mutf8::encode(name);
+        if bytes.len() > u16::MAX as usize {
+            bail!("String of length {} is too long", bytes.len());
+        }
+        self.buffer.write_u16::<BigEndian>(bytes.len() as u16)?;
+        self.buffer.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn write_list(&mut self, list: &List) -> Result<()> {
+        let items = &list.items;
+        // Empty lists keep the element type they were read with
+        let type_id = items
+            .first()
+            .map_or(list.element_type, |item| item.ty.type_id());
+        if items.iter().any(|item| item.ty.type_id() != type_id) {
+            bail!("List contains values of different types");
+        }
+        self.buffer.write_u8(type_id)?;
+        self.write_length(items.len())?;
+        for item in items {
+            self.write_value(&item.ty)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for NBTWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}